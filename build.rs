@@ -0,0 +1,39 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Trimmed stdout of a command, or "unknown" when it cannot be run.
+fn output_of(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|out| out.trim().to_owned())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    // Honours reproducible-build tooling when set.
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+            .to_string()
+    });
+
+    println!(
+        "cargo:rustc-env=COLONOSCOPY_GIT_SHA={}",
+        output_of("git", &["rev-parse", "HEAD"])
+    );
+    println!(
+        "cargo:rustc-env=COLONOSCOPY_RUSTC_VERSION={}",
+        output_of(&rustc, &["--version"])
+    );
+    println!("cargo:rustc-env=COLONOSCOPY_BUILD_TIMESTAMP={timestamp}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
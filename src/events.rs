@@ -0,0 +1,261 @@
+use crate::poller::log_py_err;
+use crate::types::{ServiceStatus, StatusColor};
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, OnceLock, Weak,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+/// Where a server publishes its transitions: to the servers started through
+/// the module-level functions, which share the module-level `on_change` and
+/// `events_queue`, or to one `Medic`.
+#[derive(Default)]
+pub struct Events {
+    /// Callables registered through `on_change`.
+    listeners: Mutex<Vec<PyObject>>,
+    /// Buffers behind the queues handed out by `events_queue`. Weak, so a
+    /// queue stops receiving events once Python drops it.
+    queues: Mutex<Vec<Weak<EventBuffer>>>,
+}
+
+/// The events of the module-level servers.
+static MODULE_EVENTS: OnceLock<Arc<Events>> = OnceLock::new();
+
+pub fn module_events() -> Arc<Events> {
+    MODULE_EVENTS.get_or_init(Arc::default).clone()
+}
+
+/// A node whose color differs between two consecutive trees.
+pub struct Transition {
+    /// Dotted path from the root, empty for the root itself.
+    pub path: String,
+    /// `None` when the node was not in the previous tree.
+    pub old: Option<StatusColor>,
+    pub new: StatusColor,
+    pub description: Option<String>,
+}
+
+/// The nodes of `new` whose color changed since `old`, keyed by path.
+/// Nodes that disappeared are not reported.
+pub fn diff(old: &ServiceStatus, new: &ServiceStatus) -> Vec<Transition> {
+    let before: HashMap<String, StatusColor> = old
+        .walk(".")
+        .into_iter()
+        .map(|(path, node)| (path, node.status))
+        .collect();
+
+    new.walk(".")
+        .into_iter()
+        .filter_map(|(path, node)| {
+            let old = before.get(&path).copied();
+            (old != Some(node.status)).then(|| Transition {
+                path,
+                old,
+                new: node.status,
+                description: node.description.clone(),
+            })
+        })
+        .collect()
+}
+
+fn call_listener(py: Python<'_>, listener: &PyAny, change: &Transition) -> PyResult<()> {
+    let result = listener.call1((
+        change.path.as_str(),
+        change.old,
+        change.new,
+        change.description.clone(),
+    ))?;
+    if result.hasattr("__await__")? {
+        let locals = pyo3_asyncio::tokio::get_current_locals(py)?;
+        let fut = pyo3_asyncio::into_future_with_locals(&locals, result)?;
+        tokio::spawn(async move {
+            if let Err(e) = fut.await {
+                log_py_err("on_change callback raised", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// A transition as delivered through an `events_queue`:
+/// `(timestamp, path, old, new)`.
+type Event = (f64, String, Option<StatusColor>, StatusColor);
+
+/// Bounded FIFO of events. Full buffers drop their oldest event so the
+/// poller never waits on a slow consumer.
+struct EventBuffer {
+    events: Mutex<VecDeque<Event>>,
+    /// Zero means unbounded, as for `asyncio.Queue`.
+    maxsize: usize,
+    dropped: AtomicU64,
+    ready: Notify,
+}
+
+impl EventBuffer {
+    fn push(&self, event: Event) {
+        let mut events = self.events.lock().unwrap();
+        if self.maxsize > 0 && events.len() >= self.maxsize {
+            events.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        events.push_back(event);
+        drop(events);
+        self.ready.notify_one();
+    }
+
+    fn try_pop(&self) -> Option<Event> {
+        self.events.lock().unwrap().pop_front()
+    }
+
+    async fn pop(&self) -> Event {
+        loop {
+            let ready = self.ready.notified();
+            if let Some(event) = self.try_pop() {
+                return event;
+            }
+            ready.await;
+        }
+    }
+}
+
+impl Events {
+    /// Pushes each transition to every live queue.
+    fn enqueue(&self, changes: &[Transition]) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let mut queues = self.queues.lock().unwrap();
+        queues.retain(|queue| queue.strong_count() > 0);
+        for queue in queues.iter().filter_map(Weak::upgrade) {
+            for change in changes {
+                queue.push((timestamp, change.path.clone(), change.old, change.new));
+            }
+        }
+    }
+
+    /// Publishes transitions to every queue and calls every listener with
+    /// each of them. Coroutines are scheduled on the poller's event loop
+    /// rather than awaited, so a slow listener never delays polling. Must
+    /// run inside the poller's task scope.
+    pub fn notify(&self, changes: &[Transition]) {
+        if changes.is_empty() {
+            return;
+        }
+        self.enqueue(changes);
+        Python::with_gil(|py| {
+            // Cloned out so a listener may itself call `on_change`.
+            let listeners: Vec<PyObject> = self
+                .listeners
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|l| l.clone_ref(py))
+                .collect();
+            for listener in &listeners {
+                for change in changes {
+                    if let Err(e) = call_listener(py, listener.as_ref(py), change) {
+                        log_py_err("on_change callback raised", e);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Backs `on_change`: registers `callback` and hands it back.
+    pub fn listen(&self, callback: &PyAny) -> PyResult<PyObject> {
+        if !callback.is_callable() {
+            return Err(PyTypeError::new_err(format!(
+                "on_change callback {} is not callable",
+                callback.repr()?
+            )));
+        }
+        self.listeners.lock().unwrap().push(callback.into());
+        Ok(callback.into())
+    }
+
+    /// Backs `events_queue`: a new queue receiving every transition from
+    /// now on.
+    pub fn queue(&self, maxsize: usize) -> EventQueue {
+        let buffer = Arc::new(EventBuffer {
+            events: Mutex::new(VecDeque::new()),
+            maxsize,
+            dropped: AtomicU64::new(0),
+            ready: Notify::new(),
+        });
+        self.queues.lock().unwrap().push(Arc::downgrade(&buffer));
+        EventQueue(buffer)
+    }
+}
+
+/// Registers `callback(service_path, old_status, new_status, description)`
+/// to be called whenever a published tree changes a node's color. The root
+/// is reported with an empty path and new nodes with `old_status=None`.
+/// Covers the servers started through the module-level functions; each
+/// `Medic` has its own `on_change`.
+///
+/// Returns the callback, so this also works as a decorator.
+#[pyfunction]
+pub fn on_change(callback: &PyAny) -> PyResult<PyObject> {
+    module_events().listen(callback)
+}
+
+/// Queue of `(timestamp, path, old, new)` status transitions, read like an
+/// `asyncio.Queue`. Filled by the poller; when full, the oldest event is
+/// dropped and counted in `dropped`.
+#[pyclass]
+pub struct EventQueue(Arc<EventBuffer>);
+
+#[pymethods]
+impl EventQueue {
+    /// Awaitable resolving with the next event.
+    fn get<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let buffer = self.0.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move { Ok(buffer.pop().await) })
+    }
+
+    /// Returns the next event, or raises `asyncio.QueueEmpty`.
+    fn get_nowait(&self, py: Python<'_>) -> PyResult<Event> {
+        match self.0.try_pop() {
+            Some(event) => Ok(event),
+            None => Err(PyErr::from_value(
+                py.import("asyncio")?.getattr("QueueEmpty")?.call0()?,
+            )),
+        }
+    }
+
+    fn qsize(&self) -> usize {
+        self.0.events.lock().unwrap().len()
+    }
+
+    fn empty(&self) -> bool {
+        self.qsize() == 0
+    }
+
+    fn full(&self) -> bool {
+        self.0.maxsize > 0 && self.qsize() >= self.0.maxsize
+    }
+
+    #[getter]
+    fn maxsize(&self) -> usize {
+        self.0.maxsize
+    }
+
+    /// Events discarded because the queue was full.
+    #[getter]
+    fn dropped(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns a new queue receiving every status transition published from now
+/// on by the servers started through the module-level functions; each
+/// `Medic` has its own `events_queue`. `maxsize=0` makes it unbounded.
+#[pyfunction]
+#[pyo3(signature = (maxsize = 1000))]
+pub fn events_queue(maxsize: usize) -> EventQueue {
+    module_events().queue(maxsize)
+}
@@ -0,0 +1,525 @@
+use crate::events::Events;
+use crate::probes::{
+    seconds_to_duration, Heartbeats, Probe, ProbeRegistry, PushedStatuses, Refreshes, Silences,
+};
+use crate::server::ProbeConfig;
+use crate::types::{
+    color_from_py, sanitize_description, timestamp_from_py, ServiceStatus, StatusColor,
+};
+use chrono::{DateTime, Utc};
+use pyo3::exceptions::{PyKeyError, PyRuntimeError, PyTimeoutError};
+use pyo3::prelude::*;
+use serde_json::Map;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
+use tracing::warn;
+
+/// The most recently started probe server, targeted by the module-level
+/// functions. Kept after shutdown so its last tree stays readable.
+static ACTIVE: Mutex<Option<Arc<ProbeControl>>> = Mutex::new(None);
+
+/// The latest published tree. Held in a watch channel so it can be read from
+/// any thread, including tokio workers, without blocking on the poller
+/// (readers only clone the pointer), and so waiters wake on every publish.
+#[derive(Clone)]
+pub struct SharedTree(Arc<TreeChannel>);
+
+struct TreeChannel {
+    current: watch::Sender<Arc<ServiceStatus>>,
+    /// Never marked as seen, so it reports whether anything has been
+    /// published since the initial placeholder.
+    initial: watch::Receiver<Arc<ServiceStatus>>,
+}
+
+impl SharedTree {
+    pub fn new(initial: ServiceStatus) -> Self {
+        let (current, initial) = watch::channel(Arc::new(initial));
+        Self(Arc::new(TreeChannel { current, initial }))
+    }
+
+    pub fn get(&self) -> Arc<ServiceStatus> {
+        self.0.current.borrow().clone()
+    }
+
+    pub fn publish(&self, tree: ServiceStatus) {
+        self.0.current.send_replace(Arc::new(tree));
+    }
+
+    /// Whether the poller has replaced the initial placeholder tree.
+    pub fn is_published(&self) -> bool {
+        self.0.initial.has_changed().unwrap_or(true)
+    }
+
+    /// Completes once the poller has replaced the initial placeholder tree.
+    pub async fn published(&self) {
+        let mut updates = self.0.current.subscribe();
+        while !self.is_published() {
+            if updates.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Resolves with the root, or the node at the dotted `path`, once its
+    /// color is at least `color`. Fails with `KeyError` as soon as a
+    /// published tree has no node at `path`.
+    pub async fn wait_for_color(
+        &self,
+        path: Option<&str>,
+        color: StatusColor,
+    ) -> PyResult<ServiceStatus> {
+        let mut updates = self.0.current.subscribe();
+        loop {
+            // Read before borrowing, so a tree seen as published really is.
+            let published = self.is_published();
+            let root = updates.borrow_and_update().clone();
+            match path.map_or(Some(&*root), |path| {
+                root.find(&path.split('.').collect::<Vec<_>>())
+            }) {
+                Some(node) if node.status >= color => return Ok(node.clone()),
+                None if published => {
+                    return Err(PyKeyError::new_err(path.unwrap_or_default().to_owned()))
+                }
+                _ => {}
+            }
+            updates
+                .changed()
+                .await
+                .map_err(|_| PyRuntimeError::new_err("health tree was dropped"))?;
+        }
+    }
+}
+
+/// How far off a deadline too distant to represent is put instead.
+const FAR_FUTURE: Duration = Duration::from_secs(30 * 365 * 24 * 3600);
+
+/// `now + delay`, saturating at a far-off instant instead of overflowing on
+/// huge configured durations.
+pub fn instant_after(now: Instant, delay: Duration) -> Instant {
+    now.checked_add(delay)
+        .or_else(|| now.checked_add(FAR_FUTURE))
+        .unwrap_or(now)
+}
+
+/// State shared between a running server, its `ProbeHandle` and the
+/// module-level functions.
+///
+/// `serve` holds the receiving half of `shutdown` for its whole lifetime, so
+/// the channel closing means the listener is gone and polling has stopped.
+pub struct ProbeControl {
+    shutdown: watch::Sender<bool>,
+    pub probes: ProbeRegistry,
+    pub pushed: PushedStatuses,
+    pub heartbeats: Heartbeats,
+    pub silences: Silences,
+    pub refreshes: Refreshes,
+    pub tree: SharedTree,
+    /// Stands in for the last update until the first tree is published.
+    started: DateTime<Utc>,
+    /// How old the tree may get before it is read as Unknown.
+    stale_tree_after: Duration,
+    /// What descriptions given from Python are cut to.
+    max_description_bytes: usize,
+    pub events: Arc<Events>,
+    /// The loop spawned for this server, stopped along with it.
+    event_loop: Option<PyObject>,
+    /// The loop probes are awaited on, once polling has started.
+    pub probe_loop: OnceLock<PyObject>,
+}
+
+impl ProbeControl {
+    pub fn new(
+        shutdown: watch::Sender<bool>,
+        probes: ProbeRegistry,
+        tree: SharedTree,
+        config: &ProbeConfig,
+        events: Arc<Events>,
+        event_loop: Option<PyObject>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            shutdown,
+            probes,
+            pushed: PushedStatuses::default(),
+            heartbeats: Heartbeats::default(),
+            silences: Silences::default(),
+            refreshes: Refreshes::default(),
+            tree,
+            started: Utc::now(),
+            stale_tree_after: config.stale_tree_after,
+            max_description_bytes: config.limits.max_description_bytes,
+            events,
+            event_loop,
+            probe_loop: OnceLock::new(),
+        })
+    }
+
+    /// The current tree. Never blocks for longer than a pointer swap, so it
+    /// is safe to call from async code.
+    pub fn snapshot(&self) -> Arc<ServiceStatus> {
+        self.tree.get()
+    }
+
+    /// The current tree as served, with the time it was last updated in
+    /// the root's `last_update` metadata. Once that is over
+    /// `stale_tree_after` ago, the root is Unknown and says how old the data
+    /// is. Only the clock is read, so this still works while the poller is
+    /// wedged.
+    pub fn status(&self) -> ServiceStatus {
+        let mut root = (*self.snapshot()).clone();
+        let last_update = root.last_checked.unwrap_or(self.started);
+        let age = (Utc::now() - last_update).to_std().unwrap_or_default();
+        if age > self.stale_tree_after {
+            root.status = StatusColor::Unknown;
+            root.description = Some(format!("data is {:.0}s old", age.as_secs_f64()));
+        }
+        let last_update = serde_json::to_value(last_update).expect("timestamp serializes");
+        root.metadata
+            .get_or_insert_with(Map::new)
+            .insert("last_update".to_owned(), last_update);
+        root
+    }
+
+    /// A description or reason given from Python, sanitized and cut like a
+    /// probe's.
+    pub fn sanitize(&self, description: Option<String>) -> Option<String> {
+        description.map(|d| sanitize_description(&d, self.max_description_bytes))
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.shutdown.is_closed()
+    }
+
+    /// Signals shutdown without waiting for it, e.g. from a signal handler.
+    pub fn request_stop(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Whether the caller runs on the loop probes are awaited on, e.g. in a
+    /// probe or in the app awaiting `serve_probe`.
+    pub fn on_probe_loop(&self, py: Python<'_>) -> PyResult<bool> {
+        let Some(probe_loop) = self.probe_loop.get() else {
+            return Ok(false);
+        };
+        let running = py.import("asyncio")?.call_method0("_get_running_loop")?;
+        Ok(running.is(probe_loop.as_ref(py)))
+    }
+
+    /// Signals shutdown and blocks until the server has exited. Returns
+    /// `false` if the server was not running or was already being stopped.
+    ///
+    /// From the probes' own loop, only signals: blocking it would stall the
+    /// probes being drained until `drain_timeout`. The server then exits
+    /// once the caller yields to the loop.
+    pub fn stop(self: &Arc<Self>, py: Python<'_>) -> PyResult<bool> {
+        let was_running = self.is_running();
+        let already_stopping = self.shutdown.send_replace(true);
+        let on_probe_loop = self.on_probe_loop(py)?;
+
+        if !on_probe_loop {
+            py.allow_threads(|| {
+                pyo3_asyncio::tokio::get_runtime().block_on(self.shutdown.closed())
+            });
+        }
+
+        if already_stopping {
+            return Ok(false);
+        }
+        if self.event_loop.is_some() {
+            if on_probe_loop {
+                let control = self.clone();
+                pyo3_asyncio::tokio::get_runtime().spawn(async move {
+                    control.shutdown.closed().await;
+                    if let Err(e) = Python::with_gil(|py| control.stop_event_loop(py)) {
+                        warn!("Could not stop the probe event loop: {e}");
+                    }
+                });
+            } else {
+                self.stop_event_loop(py)?;
+            }
+        }
+        Ok(was_running)
+    }
+
+    fn stop_event_loop(&self, py: Python<'_>) -> PyResult<()> {
+        if let Some(event_loop) = &self.event_loop {
+            let event_loop = event_loop.as_ref(py);
+            event_loop.call_method1("call_soon_threadsafe", (event_loop.getattr("stop")?,))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn set_active(control: &Arc<ProbeControl>) {
+    *ACTIVE.lock().unwrap() = Some(control.clone());
+}
+
+fn last_started() -> PyResult<Arc<ProbeControl>> {
+    ACTIVE
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| PyRuntimeError::new_err("no probe server has been started"))
+}
+
+fn running() -> PyResult<Arc<ProbeControl>> {
+    last_started()
+        .ok()
+        .filter(|control| control.is_running())
+        .ok_or_else(|| PyRuntimeError::new_err("no probe server is running"))
+}
+
+/// Stops the most recently started probe server and waits for its listener
+/// to close, unless called from the loop its probes run on. Returns `False`
+/// when no server is running.
+#[pyfunction]
+pub fn stop_probe(py: Python<'_>) -> PyResult<bool> {
+    match last_started() {
+        Ok(control) => control.stop(py),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Returns a snapshot of the current health tree of the most recently
+/// started server, including after it has been stopped.
+#[pyfunction]
+pub fn get_status() -> PyResult<ServiceStatus> {
+    Ok(last_started()?.status())
+}
+
+/// Awaitable that resolves with the root, or the subservice at the dotted
+/// `path`, as soon as its color is at least `color` (Green by default).
+///
+/// Raises `TimeoutError` once `timeout` seconds have elapsed and `KeyError`
+/// when the published tree has no node at `path`.
+#[pyfunction]
+#[pyo3(signature = (color = None, timeout = 30.0, path = None))]
+pub fn wait_for_status<'py>(
+    py: Python<'py>,
+    color: Option<&PyAny>,
+    timeout: f64,
+    path: Option<String>,
+) -> PyResult<&'py PyAny> {
+    let color = color
+        .map(color_from_py)
+        .transpose()?
+        .unwrap_or(StatusColor::Green);
+    let timeout = seconds_to_duration("timeout", timeout)?;
+    let tree = last_started()?.tree.clone();
+
+    pyo3_asyncio::tokio::future_into_py(py, async move {
+        tokio::time::timeout(timeout, tree.wait_for_color(path.as_deref(), color))
+            .await
+            .map_err(|_| {
+                PyTimeoutError::new_err(format!(
+                    "{} did not reach {color:?} within {timeout:?}",
+                    path.as_deref().unwrap_or("root")
+                ))
+            })?
+    })
+}
+
+/// Registers an extra probe with the running server; it is polled from the
+/// next cycle on.
+#[pyfunction]
+#[pyo3(signature = (obj, name = None))]
+pub fn add_probe(obj: &PyAny, name: Option<String>) -> PyResult<()> {
+    running()?.probes.add(Probe::new(obj, name)?)
+}
+
+/// Inserts or replaces a subservice reported from outside the polling cycle.
+/// With `ttl_seconds`, it turns Orange ("stale") unless pushed again in time.
+#[pyfunction]
+#[pyo3(signature = (name, status, description = None, ttl_seconds = None))]
+pub fn push_status(
+    name: String,
+    status: &PyAny,
+    description: Option<String>,
+    ttl_seconds: Option<f64>,
+) -> PyResult<()> {
+    let status = color_from_py(status)?;
+    let ttl = ttl_seconds
+        .map(|s| seconds_to_duration("ttl_seconds", s))
+        .transpose()?;
+    let control = running()?;
+    control.pushed.push(
+        ServiceStatus {
+            name,
+            status,
+            description: control.sanitize(description),
+            subservices: Vec::new(),
+            labels: HashMap::new(),
+            silenced: false,
+            stale: false,
+            last_checked: None,
+            latency_ms: None,
+            metadata: None,
+            since: None,
+            uptime: None,
+            uptime_samples: None,
+            critical: true,
+        },
+        ttl,
+    );
+    Ok(())
+}
+
+/// Expected interval plus grace of a heartbeat, validated.
+pub fn heartbeat_allowance(
+    expected_interval_seconds: f64,
+    grace_seconds: f64,
+) -> PyResult<Duration> {
+    let expected = seconds_to_duration("expected_interval_seconds", expected_interval_seconds)?;
+    let grace = if grace_seconds == 0.0 {
+        Duration::ZERO
+    } else {
+        seconds_to_duration("grace_seconds", grace_seconds)?
+    };
+    Ok(expected.saturating_add(grace))
+}
+
+/// Registers a service that reports in with `beat` instead of being polled,
+/// such as a batch job. It turns Red ("no heartbeat for Xs") once
+/// `expected_interval_seconds` plus `grace_seconds` pass without a beat,
+/// counting from registration. Registering again only changes the timing.
+#[pyfunction]
+#[pyo3(signature = (name, expected_interval_seconds, grace_seconds = 0.0))]
+pub fn register_heartbeat(
+    name: String,
+    expected_interval_seconds: f64,
+    grace_seconds: f64,
+) -> PyResult<()> {
+    let allowed = heartbeat_allowance(expected_interval_seconds, grace_seconds)?;
+    running()?.heartbeats.register(name, allowed);
+    Ok(())
+}
+
+/// Checks in a service registered with `register_heartbeat`, reporting
+/// `status` (Green by default) until the next beat.
+#[pyfunction]
+#[pyo3(signature = (name, status = None, description = None))]
+pub fn beat(name: &str, status: Option<&PyAny>, description: Option<String>) -> PyResult<()> {
+    let status = status.map(color_from_py).transpose()?;
+    let status = status.unwrap_or(StatusColor::Green);
+    let control = running()?;
+    if control
+        .heartbeats
+        .beat(name, status, control.sanitize(description))
+    {
+        Ok(())
+    } else {
+        Err(PyKeyError::new_err(format!(
+            "{name:?} is not a registered heartbeat"
+        )))
+    }
+}
+
+/// `timestamp_from_py` as a `SystemTime`.
+pub fn time_from_py(arg: &str, obj: &PyAny) -> PyResult<SystemTime> {
+    timestamp_from_py(arg, obj).map(SystemTime::from)
+}
+
+/// Puts a service in maintenance: it keeps its color in the tree but is
+/// marked `silenced` and ignored when aggregating its parent, until
+/// `unsilence` is called or the `until` datetime passes. Grouped services
+/// are named by their path, e.g. `"storage.db"`.
+#[pyfunction]
+#[pyo3(signature = (name, reason = None, until = None))]
+pub fn silence(name: String, reason: Option<String>, until: Option<&PyAny>) -> PyResult<()> {
+    let until = until.map(|u| time_from_py("until", u)).transpose()?;
+    let control = running()?;
+    control
+        .silences
+        .silence(name, control.sanitize(reason), until);
+    Ok(())
+}
+
+/// Ends the maintenance of a service silenced with `silence`.
+#[pyfunction]
+pub fn unsilence(name: &str) -> PyResult<()> {
+    if running()?.silences.unsilence(name) {
+        Ok(())
+    } else {
+        Err(PyKeyError::new_err(name.to_owned()))
+    }
+}
+
+/// Unregisters a probe by name; its subservice disappears from the next
+/// published tree.
+#[pyfunction]
+pub fn remove_probe(name: &str) -> PyResult<()> {
+    if running()?.probes.remove(name) {
+        Ok(())
+    } else {
+        Err(PyKeyError::new_err(name.to_owned()))
+    }
+}
+
+/// Handle to a probe server started with `start_probe`.
+///
+/// Dropping the handle does not stop the server; it keeps running until
+/// `stop()` is called or the interpreter exits.
+#[pyclass]
+pub struct ProbeHandle {
+    control: Arc<ProbeControl>,
+    task: Mutex<Option<JoinHandle<PyResult<()>>>>,
+    /// Where the listener is bound, with the URL it serves from; `None` for
+    /// `start_polling`.
+    listening: Option<(SocketAddr, String)>,
+}
+
+impl ProbeHandle {
+    pub fn new(
+        control: Arc<ProbeControl>,
+        task: JoinHandle<PyResult<()>>,
+        listening: Option<(SocketAddr, String)>,
+    ) -> Self {
+        Self {
+            control,
+            task: Mutex::new(Some(task)),
+            listening,
+        }
+    }
+
+    pub fn control(&self) -> &Arc<ProbeControl> {
+        &self.control
+    }
+}
+
+#[pymethods]
+impl ProbeHandle {
+    /// Gracefully stops the server and the polling task. Idempotent. From
+    /// the probes' own loop, returns without waiting for them to drain.
+    pub fn stop(&self, py: Python<'_>) -> PyResult<()> {
+        self.control.stop(py)?;
+        if self.control.on_probe_loop(py)? {
+            return Ok(());
+        }
+
+        let Some(task) = self.task.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let joined = py.allow_threads(|| pyo3_asyncio::tokio::get_runtime().block_on(task));
+        joined.map_err(|e| PyRuntimeError::new_err(format!("probe server panicked: {e}")))?
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.control.is_running()
+    }
+
+    /// The port actually bound, which differs from the requested one with
+    /// `port=0`. `None` when there is no listener.
+    #[getter]
+    pub fn port(&self) -> Option<u16> {
+        self.listening.as_ref().map(|(addr, _)| addr.port())
+    }
+
+    /// Base URL of the routes, e.g. `http://127.0.0.1:41327/internal`.
+    /// `None` when there is no listener.
+    #[getter]
+    pub fn url(&self) -> Option<String> {
+        self.listening.as_ref().map(|(_, url)| url.clone())
+    }
+}
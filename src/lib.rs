@@ -1,14 +1,61 @@
+// pyo3 0.20 expands #[pymethods] constructors into impls the
+// non_local_definitions lint flags on current toolchains.
+#![allow(non_local_definitions)]
+
+mod events;
+mod handle;
+mod medic;
+mod poller;
+mod probes;
 mod server;
+mod signals;
 mod types;
+mod version;
 
+use events::{events_queue, on_change, EventQueue};
+use handle::{
+    add_probe, beat, get_status, push_status, register_heartbeat, remove_probe, silence,
+    stop_probe, unsilence, wait_for_status, ProbeHandle,
+};
+use medic::Medic;
+use poller::default_aggregate;
+use probes::{probe, ProbeDecorator, ProbeSpec};
 use pyo3::prelude::*;
-use server::set_probe;
+use server::{check_once, render_health, run, serve_probe, set_probe, start_polling, start_probe};
 use types::{ServiceStatus, StatusColor};
+use version::build_info;
 
 #[pymodule]
 fn colonoscopy(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_function(wrap_pyfunction!(build_info, m)?)?;
     m.add_function(wrap_pyfunction!(set_probe, m)?)?;
+    m.add_function(wrap_pyfunction!(serve_probe, m)?)?;
+    m.add_function(wrap_pyfunction!(start_probe, m)?)?;
+    m.add_function(wrap_pyfunction!(start_polling, m)?)?;
+    m.add_function(wrap_pyfunction!(render_health, m)?)?;
+    m.add_function(wrap_pyfunction!(stop_probe, m)?)?;
+    m.add_function(wrap_pyfunction!(add_probe, m)?)?;
+    m.add_function(wrap_pyfunction!(remove_probe, m)?)?;
+    m.add_function(wrap_pyfunction!(push_status, m)?)?;
+    m.add_function(wrap_pyfunction!(register_heartbeat, m)?)?;
+    m.add_function(wrap_pyfunction!(beat, m)?)?;
+    m.add_function(wrap_pyfunction!(silence, m)?)?;
+    m.add_function(wrap_pyfunction!(unsilence, m)?)?;
+    m.add_function(wrap_pyfunction!(get_status, m)?)?;
+    m.add_function(wrap_pyfunction!(wait_for_status, m)?)?;
+    m.add_function(wrap_pyfunction!(on_change, m)?)?;
+    m.add_function(wrap_pyfunction!(events_queue, m)?)?;
+    m.add_function(wrap_pyfunction!(probe, m)?)?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    m.add_function(wrap_pyfunction!(check_once, m)?)?;
+    m.add_function(wrap_pyfunction!(default_aggregate, m)?)?;
     m.add_class::<StatusColor>()?;
     m.add_class::<ServiceStatus>()?;
+    m.add_class::<ProbeHandle>()?;
+    m.add_class::<ProbeDecorator>()?;
+    m.add_class::<ProbeSpec>()?;
+    m.add_class::<EventQueue>()?;
+    m.add_class::<Medic>()?;
     Ok(())
 }
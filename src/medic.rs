@@ -0,0 +1,284 @@
+use crate::events::{EventQueue, Events};
+use crate::handle::{heartbeat_allowance, time_from_py, ProbeHandle};
+use crate::probes::{seconds_to_duration, Probe, ProbeRegistry};
+use crate::server::{start_in_background, Logging, Options, ProbeConfig};
+use crate::types::{color_from_py, ServiceStatus, StatusColor};
+use pyo3::exceptions::{PyKeyError, PyRuntimeError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// An independent probe server with its own probes, tree, listener and
+/// change events.
+///
+/// Unlike `start_probe`, instances are never the target of the module-level
+/// functions, so several can run side by side on different ports.
+#[pyclass]
+pub struct Medic {
+    config: ProbeConfig,
+    logging: Logging,
+    probes: ProbeRegistry,
+    /// Kept across runs, so listeners and queues outlive `stop()`.
+    events: Arc<Events>,
+    /// The current or last run, kept after `stop()` so its tree stays
+    /// readable.
+    handle: Mutex<Option<Arc<ProbeHandle>>>,
+}
+
+impl Medic {
+    fn last_run(&self) -> PyResult<Arc<ProbeHandle>> {
+        self.handle
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| PyRuntimeError::new_err("this Medic has not been started"))
+    }
+
+    fn running(&self) -> PyResult<Arc<ProbeHandle>> {
+        let handle = self.last_run()?;
+        if !handle.is_running() {
+            return Err(PyRuntimeError::new_err(format!(
+                "Medic {:?} is not running",
+                self.config.root_name
+            )));
+        }
+        Ok(handle)
+    }
+}
+
+#[pymethods]
+impl Medic {
+    #[new]
+    #[pyo3(signature = (
+        name = String::from("medic"),
+        host = String::from("0.0.0.0"),
+        port = 3000,
+        services = None,
+        **options
+    ))]
+    #[pyo3(
+        text_signature = "(name='medic', host='0.0.0.0', port=3000, services=None, *, \
+        interval_seconds=5.0, stale_tree_intervals=3.0, allow_sync=False, \
+        probe_timeout_seconds=10.0, fresh_timeout_seconds=10.0, drain_timeout_seconds=5.0, \
+        startup_grace_seconds=None, retries=0, retry_delay_seconds=0.5, \
+        cycle_deadline_seconds=None, max_concurrency=None, aggregator=None, aggregation='worst', \
+        non_critical_orange_degrades=False, empty_status=None, root_description=None, \
+        dashboard=True, dashboard_path='/', path_prefix='', allow_duplicates=False, on_start=None, \
+        on_stop=None, lenient_status=False, uptime_window_seconds=3600.0, colors=None, \
+        strict_status=False, max_stale_polls=3, eager_first_poll=True, \
+        startup_timeout_seconds=10.0, jitter_fraction=0.1, backoff_after=None, backoff_factor=2.0, \
+        max_backoff_seconds=300.0, circuit_breaker_after=None, \
+        circuit_breaker_cooldown_seconds=60.0, failure_threshold=1, success_threshold=1, \
+        flap_window=20, flap_threshold=None, flap_quiet_seconds=60.0, max_depth=32, \
+        max_nodes=10000, max_description_bytes=1024, log_level='info', log_json=False, \
+        log_filter=None)"
+    )]
+    fn new(
+        py: Python<'_>,
+        name: String,
+        host: String,
+        port: u16,
+        services: Option<&PyAny>,
+        options: Option<&PyDict>,
+    ) -> PyResult<Self> {
+        let options = Options::new(py, "Medic", options)?.exclude(&["root_name"])?;
+        let logging = Logging::from_options(&options)?;
+        let config = ProbeConfig {
+            host,
+            port,
+            root_name: name,
+            ..ProbeConfig::from_options(&options)?
+        };
+        options.finish()?;
+        Ok(Self {
+            probes: ProbeRegistry::from_py(services, config.allow_duplicates)?,
+            config,
+            logging,
+            events: Arc::default(),
+            handle: Mutex::new(None),
+        })
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        &self.config.root_name
+    }
+
+    /// Binds the listener and starts polling in the background. Raises if
+    /// the port is unavailable or this instance is already running.
+    fn start(&self, py: Python<'_>) -> PyResult<()> {
+        if self.is_running() {
+            return Err(PyRuntimeError::new_err(format!(
+                "Medic {:?} is already running",
+                self.config.root_name
+            )));
+        }
+        self.logging.init()?;
+        // Started outside the lock: binding releases the GIL, which another
+        // thread waiting on the lock might be holding.
+        let started = start_in_background(
+            py,
+            self.probes.clone(),
+            self.config.clone(),
+            self.events.clone(),
+        )?;
+        *self.handle.lock().unwrap() = Some(Arc::new(started));
+        Ok(())
+    }
+
+    /// The port bound by the current or last run; see `ProbeHandle.port`.
+    #[getter]
+    fn port(&self) -> PyResult<Option<u16>> {
+        Ok(self.last_run()?.port())
+    }
+
+    #[getter]
+    fn url(&self) -> PyResult<Option<String>> {
+        Ok(self.last_run()?.url())
+    }
+
+    /// Gracefully stops the server. Idempotent.
+    fn stop(&self, py: Python<'_>) -> PyResult<()> {
+        let handle = self.handle.lock().unwrap().clone();
+        match handle {
+            Some(handle) => handle.stop(py),
+            None => Ok(()),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.handle
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|h| h.is_running())
+    }
+
+    /// Snapshot of this instance's tree, including after it has stopped.
+    fn get_status(&self) -> PyResult<ServiceStatus> {
+        Ok(self.last_run()?.control().status())
+    }
+
+    /// Registers an extra probe, polled from the next cycle on. Also works
+    /// before `start()`.
+    #[pyo3(signature = (obj, name = None))]
+    fn add_probe(&self, obj: &PyAny, name: Option<String>) -> PyResult<()> {
+        self.probes.add(Probe::new(obj, name)?)
+    }
+
+    fn remove_probe(&self, name: &str) -> PyResult<()> {
+        if self.probes.remove(name) {
+            Ok(())
+        } else {
+            Err(PyKeyError::new_err(name.to_owned()))
+        }
+    }
+
+    /// Same as the module-level `on_change`, for this instance's
+    /// transitions only.
+    fn on_change(&self, callback: &PyAny) -> PyResult<PyObject> {
+        self.events.listen(callback)
+    }
+
+    /// Same as the module-level `events_queue`, for this instance's
+    /// transitions only.
+    #[pyo3(signature = (maxsize = 1000))]
+    fn events_queue(&self, maxsize: usize) -> EventQueue {
+        self.events.queue(maxsize)
+    }
+
+    /// Same as the module-level `push_status`, for this instance's tree.
+    #[pyo3(signature = (name, status, description = None, ttl_seconds = None))]
+    fn push_status(
+        &self,
+        name: String,
+        status: &PyAny,
+        description: Option<String>,
+        ttl_seconds: Option<f64>,
+    ) -> PyResult<()> {
+        let status = color_from_py(status)?;
+        let ttl = ttl_seconds
+            .map(|s| seconds_to_duration("ttl_seconds", s))
+            .transpose()?;
+        let running = self.running()?;
+        let control = running.control();
+        control.pushed.push(
+            ServiceStatus {
+                name,
+                status,
+                description: control.sanitize(description),
+                subservices: Vec::new(),
+                labels: HashMap::new(),
+                silenced: false,
+                stale: false,
+                last_checked: None,
+                latency_ms: None,
+                metadata: None,
+                since: None,
+                uptime: None,
+                uptime_samples: None,
+                critical: true,
+            },
+            ttl,
+        );
+        Ok(())
+    }
+
+    /// Same as the module-level `register_heartbeat`, for this instance's
+    /// tree.
+    #[pyo3(signature = (name, expected_interval_seconds, grace_seconds = 0.0))]
+    fn register_heartbeat(
+        &self,
+        name: String,
+        expected_interval_seconds: f64,
+        grace_seconds: f64,
+    ) -> PyResult<()> {
+        let allowed = heartbeat_allowance(expected_interval_seconds, grace_seconds)?;
+        self.running()?.control().heartbeats.register(name, allowed);
+        Ok(())
+    }
+
+    #[pyo3(signature = (name, status = None, description = None))]
+    fn beat(
+        &self,
+        name: &str,
+        status: Option<&PyAny>,
+        description: Option<String>,
+    ) -> PyResult<()> {
+        let status = status.map(color_from_py).transpose()?;
+        let status = status.unwrap_or(StatusColor::Green);
+        let running = self.running()?;
+        let control = running.control();
+        if control
+            .heartbeats
+            .beat(name, status, control.sanitize(description))
+        {
+            Ok(())
+        } else {
+            Err(PyKeyError::new_err(format!(
+                "{name:?} is not a registered heartbeat"
+            )))
+        }
+    }
+
+    /// Same as the module-level `silence`, for this instance's tree.
+    #[pyo3(signature = (name, reason = None, until = None))]
+    fn silence(&self, name: String, reason: Option<String>, until: Option<&PyAny>) -> PyResult<()> {
+        let until = until.map(|u| time_from_py("until", u)).transpose()?;
+        let running = self.running()?;
+        let control = running.control();
+        control
+            .silences
+            .silence(name, control.sanitize(reason), until);
+        Ok(())
+    }
+
+    fn unsilence(&self, name: &str) -> PyResult<()> {
+        if self.running()?.control().silences.unsilence(name) {
+            Ok(())
+        } else {
+            Err(PyKeyError::new_err(name.to_owned()))
+        }
+    }
+}
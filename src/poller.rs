@@ -0,0 +1,1742 @@
+use crate::events::{diff, Events};
+use crate::handle::{instant_after, SharedTree};
+use crate::probes::{Heartbeats, Probe, ProbeRegistry, PushedStatuses, Refreshes, Silences};
+use crate::server::{Aggregation, ProbeConfig, RootDescription};
+use crate::types::{
+    color_from_py, sanitize_description, status_from_py, ServiceStatus, StatusColor,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::future::{abortable, join_all, AbortHandle};
+use futures::stream::{FuturesUnordered, StreamExt};
+use pyo3::exceptions::{
+    PyAttributeError, PyImportError, PyNameError, PyRuntimeError, PyTimeoutError, PyTypeError,
+};
+use pyo3::prelude::*;
+use pyo3_asyncio::tokio::into_future;
+use serde_json::Map;
+use std::{
+    cell::RefCell,
+    collections::{hash_map::RandomState, HashMap, HashSet, VecDeque},
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{sync::watch, time::Instant};
+use tracing::{debug, error, info, warn};
+
+pub fn log_py_err(msg: &str, err: PyErr) {
+    Python::with_gil(|py| {
+        error!("{msg}: {:?}", err);
+        err.print(py);
+    });
+}
+
+/// Invokes a probe and awaits its result when it is awaitable; synchronous
+/// probes returning a dict or `ServiceStatus` are used as-is.
+///
+/// With `allow_sync`, the call itself runs on the blocking pool so a slow
+/// synchronous `health()` cannot stall the runtime. With a `timeout`,
+/// awaitables go through `asyncio.wait_for`, so the Python task is cancelled
+/// rather than left running once the poller gives up on it.
+async fn call_probe(
+    probe: &Arc<Probe>,
+    allow_sync: bool,
+    timeout: Option<Duration>,
+) -> PyResult<PyObject> {
+    let result = if allow_sync {
+        let probe = probe.clone();
+        tokio::task::spawn_blocking(move || {
+            Python::with_gil(|py| probe.invoke(py).map(PyObject::from))
+        })
+        .await
+        .map_err(|e| PyRuntimeError::new_err(format!("probe panicked: {e}")))??
+    } else {
+        Python::with_gil(|py| probe.invoke(py).map(PyObject::from))?
+    };
+
+    let fut = Python::with_gil(|py| {
+        let result = result.as_ref(py);
+        if !result.hasattr("__await__")? {
+            return Ok(None);
+        }
+        let awaitable = match timeout {
+            Some(timeout) => py
+                .import("asyncio")?
+                .call_method1("wait_for", (result, timeout.as_secs_f64()))?,
+            None => result,
+        };
+        into_future(awaitable).map(Some)
+    })?;
+    match fut {
+        Some(fut) => fut.await,
+        None => Ok(result),
+    }
+}
+
+/// Why a single attempt at a probe produced no status.
+enum Failure {
+    TimedOut(Duration),
+    Raised(PyErr),
+    /// The probe answered with something that is not a status.
+    Unreadable(PyErr),
+}
+
+impl Failure {
+    fn describe(&self) -> String {
+        match self {
+            Self::TimedOut(timeout) => {
+                format!("health check timed out after {}s", timeout.as_secs_f64())
+            }
+            // `Type: message`, first line only.
+            Self::Raised(e) => e.to_string().lines().next().unwrap_or_default().to_owned(),
+            Self::Unreadable(e) => format!("unreadable result: {e}"),
+        }
+    }
+
+    /// Whether the probe raised an error pointing at its own code rather
+    /// than at what it checks.
+    fn is_probe_bug(&self) -> bool {
+        let Self::Raised(e) = self else {
+            return false;
+        };
+        Python::with_gil(|py| {
+            e.is_instance_of::<PyAttributeError>(py)
+                || e.is_instance_of::<PyImportError>(py)
+                || e.is_instance_of::<PyNameError>(py)
+                || e.is_instance_of::<PyTypeError>(py)
+        })
+    }
+
+    /// Red, unless the check ran but its result could not be read.
+    fn color(&self) -> StatusColor {
+        match self {
+            Self::Unreadable(_) => StatusColor::Unknown,
+            _ => StatusColor::Red,
+        }
+    }
+}
+
+/// Calls a probe once under its own timeout, else the global one, else
+/// `fallback_timeout`, and converts the result.
+async fn attempt_probe(
+    probe: &Arc<Probe>,
+    config: &ProbeConfig,
+    fallback_timeout: Option<Duration>,
+) -> Result<ServiceStatus, Failure> {
+    let called = match probe.timeout.or(config.timeout).or(fallback_timeout) {
+        Some(timeout) => {
+            let called =
+                tokio::time::timeout(timeout, call_probe(probe, config.allow_sync, Some(timeout)))
+                    .await;
+            match called {
+                Ok(Err(e)) if Python::with_gil(|py| e.is_instance_of::<PyTimeoutError>(py)) => {
+                    return Err(Failure::TimedOut(timeout))
+                }
+                Ok(called) => called,
+                Err(_) => return Err(Failure::TimedOut(timeout)),
+            }
+        }
+        None => call_probe(probe, config.allow_sync, None).await,
+    };
+
+    let result = called.map_err(Failure::Raised)?;
+    Python::with_gil(|py| {
+        status_from_py(
+            result.as_ref(py),
+            Some(&probe.name),
+            config.lenient_status,
+            config.strict_status,
+            &config.limits,
+        )
+    })
+    .map_err(Failure::Unreadable)
+}
+
+/// Runs one probe, stamps the result with the time and labels it with the
+/// probe's tags, keeping any label the result sets itself.
+///
+/// Every probe yields a node, even when it raises, times out or returns
+/// something unreadable, so that a failing probe counts in the aggregate
+/// rather than dropping out of the tree. With `max_concurrency`, the poll
+/// first waits for one of the probes in flight to finish.
+async fn poll_probe(
+    probe: &Arc<Probe>,
+    config: &ProbeConfig,
+    fallback_timeout: Option<Duration>,
+) -> Polled {
+    let _permit = match &config.max_concurrency {
+        Some((limit, permits)) => {
+            let permit = permits.acquire().await.expect("permits are never closed");
+            debug!(
+                "probe {}: {} of {limit} polls in flight",
+                probe.name,
+                limit - permits.available_permits()
+            );
+            Some(permit)
+        }
+        None => None,
+    };
+    let mut polled = retry_probe(probe, config, fallback_timeout).await;
+    let status = polled.status_mut();
+    status.last_checked = Some(Utc::now());
+    status.critical &= probe.critical;
+    for (key, value) in &probe.tags {
+        status
+            .labels
+            .entry(key.clone())
+            .or_insert_with(|| value.clone());
+    }
+    polled
+}
+
+/// How polling a probe went, with the node to report for it.
+enum Polled {
+    Read(ServiceStatus),
+    Failed(ServiceStatus),
+    /// The probe raised an error that points at its own code.
+    Broken(ServiceStatus),
+    /// The probe answered, but not with anything readable as a status.
+    Unreadable(ServiceStatus),
+}
+
+impl Polled {
+    fn status_mut(&mut self) -> &mut ServiceStatus {
+        match self {
+            Self::Read(status)
+            | Self::Failed(status)
+            | Self::Broken(status)
+            | Self::Unreadable(status) => status,
+        }
+    }
+
+    fn into_status(self) -> ServiceStatus {
+        match self {
+            Self::Read(status)
+            | Self::Failed(status)
+            | Self::Broken(status)
+            | Self::Unreadable(status) => status,
+        }
+    }
+}
+
+/// Runs one probe, retrying failed attempts up to its own retry count, else
+/// the global one. Only the last failure is logged and reported, as an
+/// entry named after the probe: Unknown for unreadable results, else Red.
+/// The result carries the latency of the last attempt, which is the timeout
+/// itself when it timed out.
+async fn retry_probe(
+    probe: &Arc<Probe>,
+    config: &ProbeConfig,
+    fallback_timeout: Option<Duration>,
+) -> Polled {
+    let attempts = probe.retries.unwrap_or(config.retries) + 1;
+    let mut attempt = 1;
+    let (failure, latency) = loop {
+        let started = Instant::now();
+        let attempted = attempt_probe(probe, config, fallback_timeout).await;
+        let latency = match &attempted {
+            Err(Failure::TimedOut(timeout)) => *timeout,
+            _ => started.elapsed(),
+        };
+        match attempted {
+            Ok(mut status) => {
+                status.latency_ms = Some(latency.as_secs_f64() * 1000.0);
+                return Polled::Read(status);
+            }
+            Err(failure) if attempt < attempts => {
+                debug!(
+                    "probe {} attempt {attempt}/{attempts} failed: {}",
+                    probe.name,
+                    failure.describe()
+                );
+                tokio::time::sleep(config.retry_delay).await;
+                attempt += 1;
+            }
+            Err(failure) => break (failure, latency),
+        }
+    };
+
+    let description = match attempts {
+        1 => failure.describe(),
+        _ => format!("failed after {attempts} attempts: {}", failure.describe()),
+    };
+    let description = sanitize_description(&description, config.limits.max_description_bytes);
+    let status = failure.color();
+    let unreadable = matches!(failure, Failure::Unreadable(_));
+    let broken = failure.is_probe_bug();
+    match failure {
+        Failure::TimedOut(_) => warn!("probe {}: {description}", probe.name),
+        Failure::Raised(e) | Failure::Unreadable(e) => {
+            log_py_err(&format!("probe {} failed", probe.name), e)
+        }
+    }
+    let status = ServiceStatus {
+        name: probe.name.clone(),
+        status,
+        description: Some(description),
+        subservices: Vec::new(),
+        labels: HashMap::new(),
+        silenced: false,
+        stale: false,
+        last_checked: None,
+        latency_ms: Some(latency.as_secs_f64() * 1000.0),
+        metadata: None,
+        since: None,
+        uptime: None,
+        uptime_samples: None,
+        critical: true,
+    };
+    if unreadable {
+        Polled::Unreadable(status)
+    } else if broken {
+        Polled::Broken(status)
+    } else {
+        Polled::Failed(status)
+    }
+}
+
+/// All Green → Green, any Red → Red, anything else (including Unknown) →
+/// Orange.
+pub fn aggregate(colors: &[StatusColor]) -> StatusColor {
+    match colors.iter().min() {
+        None => StatusColor::Green,
+        Some(StatusColor::Unknown) => StatusColor::Orange,
+        Some(&worst) => worst,
+    }
+}
+
+/// The color `status` counts as towards its parent's: the worst found in
+/// its subtree, with Red from a non-critical service only counting as
+/// Orange, and its Orange as Green unless `orange_degrades`.
+fn counted_color(status: &ServiceStatus, critical: bool, orange_degrades: bool) -> StatusColor {
+    let color = status.rolled_up_status(orange_degrades);
+    match critical && status.critical {
+        true => color,
+        false => color.as_non_critical(orange_degrades),
+    }
+}
+
+thread_local! {
+    /// The configuration of the server whose custom `aggregator` is being
+    /// called on this thread, for `default_aggregate` to follow.
+    static AGGREGATING: RefCell<Option<(bool, Aggregation)>> = const { RefCell::new(None) };
+}
+
+/// The built-in aggregation, for composing with in a custom `aggregator`.
+/// Counts `statuses` as the server does: by the worst color in each
+/// subtree, capped for non-critical services, leaving silenced ones out
+/// and following its `aggregation` policy. Called outside an aggregator,
+/// the defaults apply.
+#[pyfunction]
+pub fn default_aggregate(statuses: Vec<ServiceStatus>) -> StatusColor {
+    let (orange_degrades, aggregation) = AGGREGATING
+        .with_borrow(Clone::clone)
+        .unwrap_or((false, Aggregation::Worst));
+    let colors: Vec<StatusColor> = statuses
+        .iter()
+        .filter(|s| !s.silenced)
+        .map(|s| counted_color(s, true, orange_degrades))
+        .collect();
+    aggregation.apply(&colors)
+}
+
+/// Calls the user-supplied aggregator with the subservices; it may return a
+/// `StatusColor` or its name.
+fn custom_aggregate(
+    config: &ProbeConfig,
+    aggregator: &PyObject,
+    sub_statuses: &[ServiceStatus],
+) -> PyResult<StatusColor> {
+    let server = (
+        config.non_critical_orange_degrades,
+        config.aggregation.clone(),
+    );
+    let previous = AGGREGATING.replace(Some(server));
+    let color = Python::with_gil(|py| {
+        let color = aggregator.call1(py, (sub_statuses.to_vec(),))?;
+        color_from_py(color.as_ref(py))
+    });
+    AGGREGATING.set(previous);
+    color
+}
+
+/// Evaluates the configured root description, sanitized like a probe's; a
+/// failing callable yields no description.
+fn describe_root(
+    config: &ProbeConfig,
+    description: &RootDescription,
+    sub_statuses: &[ServiceStatus],
+) -> Option<String> {
+    let description = match description {
+        RootDescription::Static(text) => Some(text.clone()),
+        RootDescription::Dynamic(describe) => Python::with_gil(|py| {
+            describe
+                .call1(py, (sub_statuses.to_vec(),))?
+                .extract::<Option<String>>(py)
+        })
+        .unwrap_or_else(|e| {
+            log_py_err("root_description raised", e);
+            None
+        }),
+    };
+    description.map(|d| sanitize_description(&d, config.limits.max_description_bytes))
+}
+
+/// A subservice and the probe that produced it; `None` for pushed statuses
+/// and heartbeats.
+type Entry<'a> = (Option<&'a Arc<Probe>>, ServiceStatus);
+
+fn origin(py: Python<'_>, probe: Option<&Arc<Probe>>) -> String {
+    match probe {
+        Some(probe) => match probe.target.as_ref(py).repr() {
+            Ok(repr) => repr.to_string(),
+            Err(_) => probe.name.clone(),
+        },
+        None => String::from("push_status"),
+    }
+}
+
+fn group_of(probe: Option<&Arc<Probe>>) -> Option<&str> {
+    probe.and_then(|p| p.group.as_deref())
+}
+
+/// Keeps the first entry for each name within a group. Collisions are
+/// logged once per name, tracked in `warned`.
+fn drop_duplicates<'a>(entries: Vec<Entry<'a>>, warned: &mut HashSet<String>) -> Vec<Entry<'a>> {
+    let mut kept: Vec<Entry<'a>> = Vec::with_capacity(entries.len());
+    for (probe, status) in entries {
+        let collision = kept
+            .iter()
+            .find(|(p, s)| s.name == status.name && group_of(*p) == group_of(probe));
+        match collision {
+            Some((first, _)) => {
+                if warned.insert(status.name.clone()) {
+                    let (first, other) =
+                        Python::with_gil(|py| (origin(py, *first), origin(py, probe)));
+                    warn!(
+                        "{first} and {other} both report {:?}; keeping the first",
+                        status.name
+                    );
+                }
+            }
+            None => kept.push((probe, status)),
+        }
+    }
+    kept
+}
+
+/// Service paths in maintenance, with the reason given for each.
+type Silenced = HashMap<String, Option<String>>;
+
+/// Marks a service as in maintenance, appending the reason to its
+/// description, which still fits in `max_description_bytes`.
+fn silence_status(status: &mut ServiceStatus, reason: Option<&str>, max_description_bytes: usize) {
+    status.silenced = true;
+    if let Some(reason) = reason {
+        let description = match status.description.take() {
+            Some(description) => format!("{description} (silenced: {reason})"),
+            None => format!("silenced: {reason}"),
+        };
+        status.description = Some(sanitize_description(&description, max_description_bytes));
+    }
+}
+
+/// Aggregates the color of a node at `path` with `entries` as children,
+/// from the worst color found in each child's subtree rather than its own.
+/// Silenced children are marked and left out. Without a custom aggregator,
+/// Red from a non-critical probe or service only counts as Orange, and its
+/// Orange as Green unless `non_critical_orange_degrades`; a failing
+/// aggregator falls back to the same rule. The children keep their colors.
+fn aggregate_entries(
+    config: &ProbeConfig,
+    path: Option<&str>,
+    entries: Vec<Entry<'_>>,
+    silenced: &Silenced,
+) -> (StatusColor, Vec<ServiceStatus>) {
+    let mut sub_statuses = Vec::with_capacity(entries.len());
+    let mut colors = Vec::with_capacity(entries.len());
+    for (probe, mut status) in entries {
+        let child_path = match path {
+            Some(path) => format!("{path}.{}", status.name),
+            None => status.name.clone(),
+        };
+        match silenced.get(&child_path) {
+            Some(reason) => silence_status(
+                &mut status,
+                reason.as_deref(),
+                config.limits.max_description_bytes,
+            ),
+            None => colors.push(counted_color(
+                &status,
+                probe.is_none_or(|p| p.critical),
+                config.non_critical_orange_degrades,
+            )),
+        }
+        sub_statuses.push(status);
+    }
+
+    let status = match &config.aggregator {
+        Some(aggregator) => {
+            let counted: Vec<ServiceStatus> = sub_statuses
+                .iter()
+                .filter(|s| !s.silenced)
+                .cloned()
+                .collect();
+            custom_aggregate(config, aggregator, &counted).unwrap_or_else(|e| {
+                log_py_err("aggregator raised", e);
+                aggregate(&colors)
+            })
+        }
+        None => config.aggregation.apply(&colors),
+    };
+    (status, sub_statuses)
+}
+
+/// A child of the root: an entry, or the members of a probe group.
+enum Child<'a> {
+    Entry(Entry<'a>),
+    Group(&'a str, Vec<Entry<'a>>),
+}
+
+/// Builds the root from `entries`, in order. Grouped probes are gathered
+/// under a node per group, placed where the group's first member is and
+/// aggregated like the root. Unless duplicates are allowed, only the first
+/// entry for each name within a group is kept. The built-in aggregation
+/// used, if any, goes into the root's `aggregation` metadata. With no
+/// entries at all, nothing is being checked and the root says so in
+/// `empty_status`.
+fn build_tree(
+    config: &ProbeConfig,
+    entries: Vec<Entry<'_>>,
+    warned: &mut HashSet<String>,
+    silenced: &Silenced,
+) -> ServiceStatus {
+    let entries = if config.allow_duplicates {
+        entries
+    } else {
+        drop_duplicates(entries, warned)
+    };
+
+    let mut children: Vec<Child<'_>> = Vec::new();
+    for entry in entries {
+        let Some(group) = group_of(entry.0) else {
+            children.push(Child::Entry(entry));
+            continue;
+        };
+        let members = children.iter_mut().find_map(|child| match child {
+            Child::Group(name, members) if *name == group => Some(members),
+            _ => None,
+        });
+        match members {
+            Some(members) => members.push(entry),
+            None => children.push(Child::Group(group, vec![entry])),
+        }
+    }
+    let children = children
+        .into_iter()
+        .map(|child| match child {
+            Child::Entry(entry) => entry,
+            Child::Group(name, members) => {
+                let (status, subservices) =
+                    aggregate_entries(config, Some(name), members, silenced);
+                let node = ServiceStatus {
+                    name: name.to_owned(),
+                    status,
+                    description: None,
+                    subservices,
+                    labels: HashMap::new(),
+                    silenced: false,
+                    stale: false,
+                    last_checked: None,
+                    latency_ms: None,
+                    metadata: None,
+                    since: None,
+                    uptime: None,
+                    uptime_samples: None,
+                    critical: true,
+                };
+                (None, node)
+            }
+        })
+        .collect();
+
+    let (status, sub_statuses) = aggregate_entries(config, None, children, silenced);
+    let (status, description) = if sub_statuses.is_empty() {
+        (config.empty_status, Some("no probes registered".to_owned()))
+    } else {
+        let description = config
+            .root_description
+            .as_ref()
+            .and_then(|d| describe_root(config, d, &sub_statuses));
+        (status, description)
+    };
+    ServiceStatus {
+        name: config.root_name.clone(),
+        status,
+        description,
+        subservices: sub_statuses,
+        labels: HashMap::new(),
+        silenced: false,
+        stale: false,
+        last_checked: Some(Utc::now()),
+        latency_ms: None,
+        metadata: config.aggregator.is_none().then(|| {
+            let aggregation =
+                serde_json::to_value(&config.aggregation).expect("aggregation serializes");
+            Map::from_iter([("aggregation".to_owned(), aggregation)])
+        }),
+        since: None,
+        uptime: None,
+        uptime_samples: None,
+        critical: true,
+    }
+}
+
+/// Records in the root's `poller_restarts` metadata how many times the
+/// polling task has been started over.
+fn show_restarts(root: &mut ServiceStatus, restarts: u32) {
+    root.metadata
+        .get_or_insert_with(Map::new)
+        .insert("poller_restarts".to_owned(), restarts.into());
+}
+
+/// `tree` as published while the polling task is being started over: the
+/// root is Unknown until the next tree replaces it. It keeps the time it was
+/// last checked, since nothing new has been.
+pub fn poller_restarted(tree: &ServiceStatus, restarts: u32) -> ServiceStatus {
+    let mut root = tree.clone();
+    root.status = StatusColor::Unknown;
+    root.description = Some("poller restarted".to_owned());
+    show_restarts(&mut root, restarts);
+    root
+}
+
+/// Polls every probe exactly once, all at the same time, and returns the
+/// aggregated root. Probes still running at the cycle deadline are reported
+/// Unknown and their results discarded.
+pub async fn run_probes_once(probes: &[Arc<Probe>], config: &ProbeConfig) -> ServiceStatus {
+    let polled = join_all(probes.iter().map(|probe| async move {
+        let poll = poll_probe(probe, config, None);
+        match config.cycle_deadline {
+            Some(deadline) => tokio::time::timeout(deadline, poll).await.ok(),
+            None => Some(poll.await),
+        }
+    }))
+    .await;
+    let missed = polled.iter().filter(|polled| polled.is_none()).count();
+    if missed > 0 {
+        warn!(
+            "{missed} probe(s) missed the cycle deadline of {:?}",
+            config.cycle_deadline.unwrap_or_default()
+        );
+    }
+    let entries = probes
+        .iter()
+        .zip(polled)
+        .map(|(probe, polled)| {
+            let status = match polled {
+                Some(polled) => polled.into_status(),
+                None => deadline_exceeded(probe, None),
+            };
+            (Some(probe), status)
+        })
+        .collect();
+    build_tree(config, entries, &mut HashSet::new(), &Silenced::new())
+}
+
+/// Most samples kept per service for `uptime`, far more than a service
+/// polled at its interval gathers over a window; only guards memory against
+/// a flood of refreshes.
+const MAX_UPTIME_SAMPLES: usize = 100_000;
+
+/// Whether each service was Green at each of its recent polls, by path.
+#[derive(Default)]
+struct UptimeHistory(HashMap<String, VecDeque<(Instant, bool)>>);
+
+impl UptimeHistory {
+    /// Records `node`, at `path`, and every node below it as polled at
+    /// `now`.
+    fn sample(&mut self, path: &str, node: &ServiceStatus, now: Instant) {
+        for (sub_path, sub) in node.walk(".") {
+            let path = match sub_path.as_str() {
+                "" => path.to_owned(),
+                sub_path => format!("{path}.{sub_path}"),
+            };
+            let samples = self.0.entry(path).or_default();
+            samples.push_back((now, sub.status == StatusColor::Green));
+            if samples.len() > MAX_UPTIME_SAMPLES {
+                samples.pop_front();
+            }
+        }
+    }
+
+    /// Like `sample`, for a service that is not polled, such as a pushed
+    /// status: at most once per `interval`, however often it is published.
+    fn sample_unpolled(&mut self, node: &ServiceStatus, interval: Duration, now: Instant) {
+        let last = self.0.get(&node.name).and_then(|s| s.back());
+        if last.is_none_or(|(at, _)| now - *at >= interval) {
+            self.sample(&node.name, node, now);
+        }
+    }
+
+    /// Stamps every node of `tree` with its uptime over the last `window`:
+    /// from its own samples, or else pooled from those of the nodes below
+    /// it, as for the root and groups. Services gone from the tree are
+    /// forgotten.
+    fn stamp(&mut self, tree: &mut ServiceStatus, window: Duration, now: Instant) {
+        let paths: HashSet<String> = tree.walk(".").into_iter().map(|(p, _)| p).collect();
+        self.0.retain(|path, samples| {
+            while samples.front().is_some_and(|(at, _)| now - *at > window) {
+                samples.pop_front();
+            }
+            paths.contains(path) && !samples.is_empty()
+        });
+        self.stamp_node("", tree);
+    }
+
+    /// Stamps `node`, at `path`, and the nodes below it, returning how many
+    /// of the samples it was stamped from were Green, out of how many.
+    fn stamp_node(&self, path: &str, node: &mut ServiceStatus) -> (usize, usize) {
+        let mut below = (0, 0);
+        for sub in &mut node.subservices {
+            let sub_path = match path {
+                "" => sub.name.clone(),
+                parent => format!("{parent}.{}", sub.name),
+            };
+            let (green, total) = self.stamp_node(&sub_path, sub);
+            below = (below.0 + green, below.1 + total);
+        }
+        let (green, total) = match self.0.get(path) {
+            Some(samples) => (
+                samples.iter().filter(|(_, green)| *green).count(),
+                samples.len(),
+            ),
+            None => below,
+        };
+        (node.uptime, node.uptime_samples) = match total {
+            0 => (None, None),
+            total => (Some(green as f64 / total as f64), Some(total)),
+        };
+        (green, total)
+    }
+}
+
+/// A number drawn uniformly from `[0, 1)`, random enough to spread
+/// schedules apart.
+fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// `interval`, off by up to `fraction` of it either way. `fraction` is
+/// below 1, so the result is always positive.
+fn jittered(interval: Duration, fraction: f64) -> Duration {
+    interval.mul_f64(1.0 + fraction * (2.0 * random_unit() - 1.0))
+}
+
+/// The wait after a probe's first poll, which puts it at a random point of
+/// its interval, so that pods started together do not poll in step.
+fn first_delay(interval: Duration, fraction: f64) -> Duration {
+    if fraction == 0.0 {
+        return interval;
+    }
+    interval.mul_f64(1.0 - random_unit())
+}
+
+/// Scheduling state the poller keeps for each registered probe.
+#[derive(Default)]
+struct ProbeState {
+    next_due: Option<Instant>,
+    last: Option<ServiceStatus>,
+    /// The latest result read since the probe last failed outright.
+    last_read: Option<ServiceStatus>,
+    /// Unreadable results in a row.
+    unreadable: u32,
+    /// Failed or unreadable results in a row.
+    failures: u32,
+    /// The description of the latest of those `failures`, already
+    /// sanitized like any failure's.
+    last_error: Option<String>,
+    /// Red results in a row, failures included.
+    red: u32,
+    /// Green results in a row.
+    green: u32,
+    /// Whether the probe is reported Orange while it recovers from Red.
+    recovering: bool,
+    /// The latest color reported, or that would have been but for flapping.
+    unpinned: Option<StatusColor>,
+    /// Whether each of the latest polls changed the probe's color.
+    transitions: VecDeque<bool>,
+    /// When the probe last changed color.
+    last_transition: Option<Instant>,
+    /// Whether the probe is pinned to Orange for changing color too often.
+    flapping: bool,
+    /// The description of the latest broken or unreadable result and how
+    /// many identical ones came in a row.
+    repeated: Option<(String, u32)>,
+    circuit: Circuit,
+    /// Aborts the poll under way, if any.
+    polling: Option<AbortHandle>,
+    /// When the poll under way started.
+    polled_at: Option<Instant>,
+    /// Whether the poll under way has been reported as missing the cycle
+    /// deadline.
+    missed_deadline: bool,
+}
+
+impl Drop for ProbeState {
+    fn drop(&mut self) {
+        if let Some(polling) = &self.polling {
+            polling.abort();
+        }
+    }
+}
+
+impl ProbeState {
+    /// Takes in a poll made at `now`. For up to `max_stale_polls`
+    /// unreadable results in a row, the last readable one is reported
+    /// instead, marked stale. Failures in a row are counted in the
+    /// reported node's `consecutive_failures` metadata, next to the latest
+    /// one's `last_error`.
+    ///
+    /// Until `failure_threshold` Red results have come in a row, the node
+    /// keeps its previous color. Once Red, it is reported Orange until
+    /// `success_threshold` Green results have come in a row. The color
+    /// actually polled is then kept in its `raw_status` metadata.
+    fn record(&mut self, polled: Polled, probe: &Probe, config: &ProbeConfig, now: DateTime<Utc>) {
+        let max_stale_polls = config.max_stale_polls;
+        let failure_threshold = probe.failure_threshold.unwrap_or(config.failure_threshold);
+        let success_threshold = probe.success_threshold.unwrap_or(config.success_threshold);
+        let error = match &polled {
+            Polled::Broken(status) | Polled::Unreadable(status) => status.description.clone(),
+            _ => None,
+        };
+        self.repeated = match (error, self.repeated.take()) {
+            (Some(error), Some((previous, count))) if error == previous => Some((error, count + 1)),
+            (error, _) => error.map(|error| (error, 1)),
+        };
+        let mut status = match polled {
+            Polled::Read(status) => {
+                self.unreadable = 0;
+                self.failures = 0;
+                self.last_error = None;
+                self.last_read = Some(status.clone());
+                status
+            }
+            Polled::Failed(status) | Polled::Broken(status) => {
+                self.unreadable = 0;
+                self.failures += 1;
+                self.last_error = status.description.clone();
+                self.last_read = None;
+                status
+            }
+            Polled::Unreadable(status) => {
+                self.unreadable += 1;
+                self.failures += 1;
+                self.last_error = status.description.clone();
+                match &self.last_read {
+                    Some(read) if self.unreadable <= max_stale_polls => stale(read, now),
+                    _ => status,
+                }
+            }
+        };
+        if self.failures > 0 {
+            let metadata = status.metadata.get_or_insert_with(Map::new);
+            metadata.insert("consecutive_failures".into(), self.failures.into());
+            if let Some(error) = &self.last_error {
+                metadata.insert("last_error".into(), error.as_str().into());
+            }
+        }
+        let polled_color = status.status;
+        (self.red, self.green) = match polled_color {
+            StatusColor::Red => (self.red + 1, 0),
+            StatusColor::Green => (0, self.green + 1),
+            _ => (0, 0),
+        };
+        let previous = self.last.as_ref().map(|last| last.status);
+        let (color, note) = match (polled_color, previous) {
+            (StatusColor::Red, Some(previous))
+                if previous != StatusColor::Red && self.red < failure_threshold =>
+            {
+                let note = format!("{}/{failure_threshold} failures", self.red);
+                (previous, Some(note))
+            }
+            (StatusColor::Green, Some(previous))
+                if (previous == StatusColor::Red || self.recovering)
+                    && self.green < success_threshold =>
+            {
+                let note = format!("recovering {}/{success_threshold}", self.green);
+                (StatusColor::Orange, Some(note))
+            }
+            _ => (polled_color, None),
+        };
+        self.recovering = note.is_some() && color == StatusColor::Orange;
+        if let Some(note) = note {
+            status.description = Some(match status.description.take() {
+                Some(description) => format!("{description} ({note})"),
+                None => note,
+            });
+            status
+                .metadata
+                .get_or_insert_with(Map::new)
+                .insert("raw_status".into(), polled_color.name().into());
+            status.status = color;
+        }
+        self.damp_flapping(&mut status, config);
+        self.last = Some(status);
+    }
+
+    /// Pins `status` to Orange once its color has changed more than
+    /// `flap_threshold` times over the last `flap_window` polls, and until
+    /// it has not changed for `flap_quiet`. A pinned color does not change,
+    /// so no change events are sent meanwhile.
+    fn damp_flapping(&mut self, status: &mut ServiceStatus, config: &ProbeConfig) {
+        let Some(threshold) = config.flap_threshold else {
+            return;
+        };
+        let previous = self.unpinned.replace(status.status);
+        let changed = previous.is_some_and(|previous| previous != status.status);
+        if changed {
+            self.last_transition = Some(Instant::now());
+        }
+        self.transitions.push_back(changed);
+        while self.transitions.len() > config.flap_window {
+            self.transitions.pop_front();
+        }
+        let count = self.transitions.iter().filter(|changed| **changed).count();
+        self.flapping = if count > threshold as usize {
+            true
+        } else {
+            self.flapping
+                && self
+                    .last_transition
+                    .is_some_and(|at| at.elapsed() < config.flap_quiet)
+        };
+        if !self.flapping {
+            return;
+        }
+        let note = format!(
+            "flapping, {count} transitions in the last {} polls",
+            self.transitions.len()
+        );
+        status.description = Some(match status.description.take() {
+            Some(description) => format!("{description} ({note})"),
+            None => note,
+        });
+        let metadata = status.metadata.get_or_insert_with(Map::new);
+        metadata.insert("flapping".into(), true.into());
+        metadata
+            .entry("raw_status")
+            .or_insert_with(|| status.status.name().into());
+        status.status = StatusColor::Orange;
+    }
+
+    /// Opens the circuit once `circuit_breaker_after` identical broken or
+    /// unreadable results have come in a row, or on the first one when
+    /// half-open, and closes it otherwise. While open, the probe is
+    /// reported Unknown and disabled, and is not called until it is next
+    /// due, after `circuit_breaker_cooldown`. Returns whether it opened.
+    fn trip(&mut self, after: Option<u32>) -> bool {
+        let count = self.repeated.as_ref().map_or(0, |(_, count)| *count);
+        self.circuit = match (self.circuit, after) {
+            (Circuit::HalfOpen, Some(_)) if count > 0 => Circuit::Open,
+            (_, Some(after)) if count >= after => Circuit::Open,
+            _ => Circuit::Closed,
+        };
+        let Some(last) = &mut self.last else {
+            return false;
+        };
+        if let (Circuit::Open, Some((error, count))) = (self.circuit, &self.repeated) {
+            last.status = StatusColor::Unknown;
+            last.description = Some(format!("probe disabled: {error} ({count} failures)"));
+            last.stale = false;
+        }
+        if count > 0 || self.circuit != Circuit::Closed {
+            self.circuit.show(last);
+        }
+        self.circuit == Circuit::Open
+    }
+
+    /// The wait before the next poll once the probe has failed at least
+    /// `backoff_after` times in a row: `interval`, multiplied by
+    /// `backoff_factor` for each failure from then on, up to
+    /// `max_backoff`. Noted in the description of the failure reported.
+    fn back_off(&mut self, interval: Duration, config: &ProbeConfig) -> Option<Duration> {
+        let after = config.backoff_after?;
+        if self.failures < after {
+            return None;
+        }
+        let times = (self.failures - after + 1).min(64) as i32;
+        let secs = interval.as_secs_f64() * config.backoff_factor.powi(times);
+        let backoff = Duration::try_from_secs_f64(secs)
+            .map_or(config.max_backoff, |backoff| {
+                backoff.min(config.max_backoff)
+            })
+            .max(interval);
+        let backoff = jittered(backoff, config.jitter_fraction);
+        if let Some(last) = self.last.as_mut().filter(|last| !last.stale) {
+            let note = format!(
+                "backing off, next attempt in {}s",
+                backoff.as_secs_f64().ceil()
+            );
+            last.description = Some(match last.description.take() {
+                Some(description) => format!("{description} ({note})"),
+                None => note,
+            });
+        }
+        Some(backoff)
+    }
+}
+
+/// Whether a probe is being called, after it kept failing because of a bug
+/// of its own.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Circuit {
+    #[default]
+    Closed,
+    /// Not called until the cooldown is over.
+    Open,
+    /// Called once to find out whether it was fixed.
+    HalfOpen,
+}
+
+impl Circuit {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+
+    /// Records the state in `status`'s `circuit` metadata.
+    fn show(self, status: &mut ServiceStatus) {
+        status
+            .metadata
+            .get_or_insert_with(Map::new)
+            .insert("circuit".into(), self.name().into());
+    }
+}
+
+/// What is reported for `probe` while its poll runs past the cycle
+/// deadline.
+fn deadline_exceeded(probe: &Probe, last: Option<&ServiceStatus>) -> ServiceStatus {
+    unknown(probe, last, "cycle deadline exceeded")
+}
+
+/// What is reported for `probe` while it is Red or has not answered yet
+/// during the startup grace period.
+fn starting_up(probe: &Probe, last: Option<&ServiceStatus>) -> ServiceStatus {
+    unknown(probe, last, "starting up")
+}
+
+/// What is reported for `probe` before it first answers: its node in the
+/// tree from `before_restart`, if any, else a node of its own.
+fn not_answered(probe: &Probe, before_restart: Option<&ServiceStatus>) -> ServiceStatus {
+    let path = probe.path();
+    let last = before_restart.and_then(|tree| {
+        tree.walk(".")
+            .into_iter()
+            .find_map(|(p, node)| (p == path).then_some(node))
+    });
+    match last {
+        Some(last) => unknown(probe, Some(last), "poller restarted"),
+        None => unknown(probe, None, "not checked yet"),
+    }
+}
+
+/// `probe`'s last result, if any, as Unknown with `description`.
+fn unknown(probe: &Probe, last: Option<&ServiceStatus>, description: &str) -> ServiceStatus {
+    let mut status = match last {
+        Some(last) => last.clone(),
+        None => ServiceStatus {
+            name: probe.name.clone(),
+            status: StatusColor::Unknown,
+            description: None,
+            subservices: Vec::new(),
+            labels: HashMap::new(),
+            silenced: false,
+            stale: false,
+            last_checked: None,
+            latency_ms: None,
+            metadata: None,
+            since: None,
+            uptime: None,
+            uptime_samples: None,
+            critical: true,
+        },
+    };
+    status.status = StatusColor::Unknown;
+    status.description = Some(description.to_owned());
+    status
+}
+
+/// `status` as reported while its probe's results cannot be read, the last
+/// unreadable one arriving at `now`.
+fn stale(status: &ServiceStatus, now: DateTime<Utc>) -> ServiceStatus {
+    let note = format!(
+        "stale: extraction failed at {}",
+        now.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    ServiceStatus {
+        description: Some(match &status.description {
+            Some(description) => format!("{description} ({note})"),
+            None => note,
+        }),
+        stale: true,
+        ..status.clone()
+    }
+}
+
+/// Polls every probe once its own interval (or the global default) has
+/// elapsed and republishes the tree from the latest result of each probe,
+/// followed by the pushed statuses and the heartbeats. A push, a beat or a
+/// change of silences triggers an immediate republish.
+///
+/// Probes run concurrently and independently: each result is published as
+/// soon as it arrives, without waiting for slower probes, and the probe is
+/// next polled once its interval, give or take `jitter_fraction` of it,
+/// has elapsed again. Nothing is published
+/// until every probe has answered once, so that the first tree is complete,
+/// or until `startup_timeout` has elapsed.
+/// Those without a timeout time out after their interval, or when their
+/// schedule is next due, so a hung probe turns Red instead of never
+/// answering. A probe answering something
+/// unreadable keeps showing its last readable result, marked stale, for up
+/// to `max_stale_polls` polls in a row. A probe failing `backoff_after`
+/// times in a row is polled exponentially less often until it next
+/// succeeds, and one that keeps failing because of a bug of its own is not
+/// called at all for a while. A probe changing color too often is reported
+/// Orange until it settles. A probe with a schedule is polled once at the
+/// start, then only when its schedule is next due, its result showing when
+/// that is in the `next_check` metadata. A poll running past
+/// `cycle_deadline` has its probe reported Unknown until its result
+/// arrives, which is then applied as usual. After a restart, the tree is
+/// left as it was until every probe has answered again or `startup_timeout`
+/// has elapsed, those yet to answer then keeping their node as Unknown, and
+/// the number of `restarts` so far is shown in the root's metadata. Until
+/// `startup_grace_until`, probes that are Red or have not answered yet are
+/// reported Unknown as starting up, and so is the root unless something
+/// else is Red. Probes asked for through `refreshes` are polled right away,
+/// unless already being polled, and their batch finished once the tree with
+/// their results is published. Once `shutdown` flips to `true`, no new poll
+/// is started and the task returns after draining the ones under way.
+///
+/// Everything runs on this one task: polls are futures in a
+/// `FuturesUnordered` rather than tasks of their own, each probe's
+/// `ProbeState` tracks when it is next due, and the loop sleeps until the
+/// earliest of those, or of any other deadline, but never longer than
+/// `interval`, so the tree is republished at least that often. Keeping all
+/// state on one task means no locking, and dropping the task drops every
+/// poll with it.
+#[allow(clippy::too_many_arguments)]
+pub async fn polling_task(
+    probes: ProbeRegistry,
+    pushed: PushedStatuses,
+    heartbeats: Heartbeats,
+    silences: Silences,
+    tree: SharedTree,
+    refreshes: Refreshes,
+    events: Arc<Events>,
+    mut shutdown: watch::Receiver<bool>,
+    config: ProbeConfig,
+    restarts: u32,
+) {
+    let mut states: HashMap<u64, ProbeState> = HashMap::new();
+    let mut warned = HashSet::new();
+    let mut uptime = UptimeHistory::default();
+    let mut in_flight = FuturesUnordered::new();
+    // Refresh batches under way, oldest first, with the probes each still
+    // waits for.
+    let mut refreshing: VecDeque<(u64, HashSet<u64>)> = VecDeque::new();
+    let startup_deadline = instant_after(Instant::now(), config.startup_timeout);
+    // What a restarted task stands in with for the probes that have not
+    // answered it yet.
+    let before_restart = (restarts > 0).then(|| tree.get());
+
+    loop {
+        if *shutdown.borrow() || shutdown.has_changed().is_err() {
+            break;
+        }
+        let probes = probes.snapshot();
+        // Dropping the state of a removed probe aborts its poll.
+        states.retain(|id, _| probes.iter().any(|p| p.id == *id));
+        for (_, waiting) in &mut refreshing {
+            waiting.retain(|id| probes.iter().any(|p| p.id == *id));
+        }
+        if let Some(batch) = refreshes.take() {
+            let waiting: HashSet<u64> = probes
+                .iter()
+                .filter(|p| batch.wants(p))
+                .map(|p| p.id)
+                .collect();
+            debug!("refreshing {} probe(s) on request", waiting.len());
+            for id in &waiting {
+                states.entry(*id).or_default().next_due = None;
+            }
+            refreshing.push_back((batch.id, waiting));
+        }
+
+        for probe in &probes {
+            let state = states.entry(probe.id).or_default();
+            if state.polling.is_some() || state.next_due.is_some_and(|due| due > Instant::now()) {
+                continue;
+            }
+            if state.circuit == Circuit::Open {
+                state.circuit = Circuit::HalfOpen;
+                if let Some(last) = &mut state.last {
+                    state.circuit.show(last);
+                }
+            }
+            let probe = probe.clone();
+            let config = &config;
+            let interval = probe.interval.unwrap_or(config.interval);
+            // A scheduled probe may run until its next occurrence, however far
+            // apart those are, rather than for the default interval.
+            let fallback_timeout = match &probe.schedule {
+                Some(_) => {
+                    let now = Utc::now();
+                    probe
+                        .next_scheduled(now)
+                        .and_then(|at| (at - now).to_std().ok())
+                }
+                None => Some(interval),
+            };
+            let (poll, handle) = abortable(async move {
+                let polled = poll_probe(&probe, config, fallback_timeout).await;
+                (probe, interval, polled)
+            });
+            state.polling = Some(handle);
+            state.polled_at = Some(Instant::now());
+            in_flight.push(poll);
+        }
+
+        // Polls past the cycle deadline are reported Unknown, but left to
+        // finish: their result is published as soon as it arrives.
+        let now = Instant::now();
+        let overdue = |state: &ProbeState| {
+            config
+                .cycle_deadline
+                .zip(state.polled_at)
+                .is_some_and(|(deadline, at)| now - at >= deadline)
+        };
+        let mut missed = 0;
+        for state in states.values_mut() {
+            if overdue(state) && !state.missed_deadline {
+                state.missed_deadline = true;
+                missed += 1;
+            }
+        }
+        if missed > 0 {
+            warn!(
+                "{missed} probe(s) missed the cycle deadline of {:?}",
+                config.cycle_deadline.unwrap_or_default()
+            );
+        }
+
+        let (pushed_statuses, next_expiry) = pushed.current(Instant::now());
+        let (heartbeat_statuses, next_overdue) = heartbeats.current(Instant::now());
+        let (silenced, next_unsilence) = silences.active(SystemTime::now());
+        let complete = probes.iter().all(|probe| {
+            states
+                .get(&probe.id)
+                .is_some_and(|s| s.last.is_some() || overdue(s))
+        });
+        // A restarted task leaves the tree marked as such in place until it
+        // has heard from every probe, as on the first start.
+        let publish = complete
+            || (tree.is_published() && restarts == 0)
+            || Instant::now() >= startup_deadline;
+        let grace = config
+            .startup_grace_until
+            .filter(|until| *until > Instant::now());
+        if publish {
+            let mut starting = false;
+            let mut polled = Vec::with_capacity(probes.len());
+            for probe in &probes {
+                let Some(state) = states.get(&probe.id) else {
+                    continue;
+                };
+                let last = match (&state.last, grace) {
+                    _ if overdue(state) => deadline_exceeded(probe, state.last.as_ref()),
+                    (Some(last), Some(_)) if last.status == StatusColor::Red => {
+                        starting = true;
+                        starting_up(probe, Some(last))
+                    }
+                    (Some(last), _) => last.clone(),
+                    (None, Some(_)) => {
+                        starting = true;
+                        starting_up(probe, None)
+                    }
+                    (None, None) => not_answered(probe, before_restart.as_deref()),
+                };
+                polled.push((Some(probe), last));
+            }
+            for status in pushed_statuses.iter().chain(&heartbeat_statuses) {
+                uptime.sample_unpolled(status, config.interval, Instant::now());
+            }
+            let entries = polled
+                .into_iter()
+                .chain(pushed_statuses.into_iter().map(|status| (None, status)))
+                .chain(heartbeat_statuses.into_iter().map(|status| (None, status)))
+                .collect();
+            let mut next = build_tree(&config, entries, &mut warned, &silenced);
+            if starting && next.status != StatusColor::Red {
+                next.status = StatusColor::Unknown;
+                next.description = Some("starting up".to_owned());
+            }
+            if restarts > 0 {
+                show_restarts(&mut next, restarts);
+            }
+            let previous = tree.get();
+            next.carry_since(Some(&previous), Utc::now());
+            uptime.stamp(&mut next, config.uptime_window, Instant::now());
+            let changes = diff(&previous, &next);
+            tree.publish(next);
+            events.notify(&changes);
+            while let Some((batch, _)) = refreshing.front().filter(|(_, w)| w.is_empty()) {
+                refreshes.finish(*batch);
+                refreshing.pop_front();
+            }
+        }
+
+        let next_due = states
+            .values()
+            .filter(|s| s.polling.is_none())
+            .filter_map(|s| s.next_due)
+            .chain(next_expiry)
+            .chain(next_overdue)
+            .chain(
+                states
+                    .values()
+                    .filter(|s| !s.missed_deadline)
+                    .filter_map(|s| {
+                        let deadline = config.cycle_deadline?;
+                        Some(instant_after(s.polled_at?, deadline))
+                    }),
+            )
+            .chain(next_unsilence.map(|left| instant_after(Instant::now(), left)))
+            .chain((!publish).then_some(startup_deadline))
+            .chain(grace)
+            // Republishing at least once per `interval` keeps the root's
+            // `last_checked` fresh while every probe is long, scheduled or
+            // backed off, so readers do not take the tree for stale.
+            .chain([instant_after(Instant::now(), config.interval)])
+            .min()
+            .expect("the republish deadline is always there");
+        tokio::select! {
+            Some(polled) = in_flight.next(), if !in_flight.is_empty() => {
+                // Aborted polls belong to removed probes.
+                if let Ok((probe, interval, polled)) = polled {
+                    if let Some(state) = states.get_mut(&probe.id) {
+                        let first = state.last.is_none();
+                        state.polling = None;
+                        state.polled_at = None;
+                        for (_, waiting) in &mut refreshing {
+                            waiting.remove(&probe.id);
+                        }
+                        state.missed_deadline = false;
+                        state.record(polled, &probe, &config, Utc::now());
+                        if let Some(last) = &state.last {
+                            let path = match &probe.group {
+                                Some(group) => format!("{group}.{}", last.name),
+                                None => last.name.clone(),
+                            };
+                            uptime.sample(&path, last, Instant::now());
+                        }
+                        let scheduled = probe.next_scheduled(Utc::now());
+                        let delay = if state.trip(config.circuit_breaker_after) {
+                            config.circuit_breaker_cooldown
+                        } else if let Some(at) = scheduled {
+                            (at - Utc::now()).to_std().unwrap_or_default()
+                        } else {
+                            match state.back_off(interval, &config) {
+                                Some(backoff) => backoff,
+                                None if first => first_delay(interval, config.jitter_fraction),
+                                None => jittered(interval, config.jitter_fraction),
+                            }
+                        };
+                        debug!(
+                            "probe {} next polled at {}",
+                            probe.name,
+                            Utc::now() + delay
+                        );
+                        state.next_due = Some(instant_after(Instant::now(), delay));
+                        if let (Some(last), Some(_)) = (&mut state.last, &probe.schedule) {
+                            let next = (Utc::now() + delay)
+                                .to_rfc3339_opts(SecondsFormat::Secs, true);
+                            last.metadata
+                                .get_or_insert_with(Map::new)
+                                .insert("next_check".into(), next.into());
+                        }
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(next_due) => {}
+            _ = pushed.changed() => {}
+            _ = heartbeats.changed() => {}
+            _ = silences.changed() => {}
+            _ = refreshes.requested() => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+
+    // Polls under way get to finish rather than being cancelled at whatever
+    // await they are at; those still running after `drain_timeout` are
+    // cancelled when `in_flight` is dropped. Their results are not
+    // published.
+    let running = in_flight.len();
+    if running == 0 {
+        return;
+    }
+    info!(
+        "Waiting up to {:?} for {running} probe(s) to finish",
+        config.drain_timeout
+    );
+    let drained = tokio::time::timeout(config.drain_timeout, async {
+        while in_flight.next().await.is_some() {}
+    })
+    .await;
+    match drained {
+        Ok(()) => info!("All {running} probe(s) finished"),
+        Err(_) => warn!(
+            "Cancelling {} of {running} probe(s) still running after {:?}",
+            in_flight.len(),
+            config.drain_timeout
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle::ProbeControl;
+    use crate::server::{spawn_event_loop, Options};
+    use pyo3::types::PyDict;
+    use pyo3_asyncio::TaskLocals;
+    use std::future::Future;
+
+    /// The configuration set_probe runs with when given `options`, a
+    /// Python dict expression.
+    fn config(options: &str) -> ProbeConfig {
+        Python::with_gil(|py| {
+            let options = py.eval(options, None, None)?.downcast()?;
+            ProbeConfig::from_options(&Options::new(py, "test", Some(options))?)
+        })
+        .unwrap()
+    }
+
+    /// A probe named `name` calling the `check` function defined by
+    /// `source`.
+    fn probe(name: &str, source: &str) -> Arc<Probe> {
+        Python::with_gil(|py| {
+            let scope = PyDict::new(py);
+            py.run(source, Some(scope), None)?;
+            let check = scope.get_item("check")?.expect("source defines check");
+            Probe::new(check, Some(name.into())).map(Arc::new)
+        })
+        .unwrap()
+    }
+
+    /// Awaits `fut` with async probes running on an event loop of their
+    /// own, as in the background servers.
+    fn on_event_loop<F>(fut: F) -> F::Output
+    where
+        F: Future + Send + 'static,
+        F::Output: Send,
+    {
+        let locals =
+            Python::with_gil(|py| TaskLocals::new(spawn_event_loop(py)?).copy_context(py)).unwrap();
+        pyo3_asyncio::tokio::get_runtime().block_on(pyo3_asyncio::tokio::scope(locals, fut))
+    }
+
+    #[tokio::test]
+    async fn raising_probes_report_their_first_line_as_red() {
+        let raising = probe(
+            "api",
+            "def check():\n    raise ConnectionError('refused\\nsee logs')",
+        );
+        let polled = retry_probe(&raising, &config("{}"), None).await;
+        assert!(matches!(polled, Polled::Failed(_)));
+        let status = polled.into_status();
+        assert_eq!(status.name, "api");
+        assert_eq!(status.status, StatusColor::Red);
+        assert_eq!(
+            status.description.as_deref(),
+            Some("ConnectionError: refused")
+        );
+
+        let buggy = probe("api", "def check():\n    return undefined_name");
+        let polled = retry_probe(&buggy, &config("{}"), None).await;
+        assert!(matches!(polled, Polled::Broken(_)));
+        assert_eq!(polled.into_status().status, StatusColor::Red);
+
+        let unreadable = probe("api", "def check():\n    return object()");
+        let polled = retry_probe(&unreadable, &config("{}"), None).await;
+        assert!(matches!(polled, Polled::Unreadable(_)));
+        assert_eq!(polled.into_status().status, StatusColor::Unknown);
+    }
+
+    #[test]
+    fn probes_are_polled_concurrently() {
+        let slow =
+            "import asyncio\nasync def check():\n    await asyncio.sleep(0.3)\n    return 'GREEN'";
+        let probes = ["a", "b", "c", "d"].map(|name| probe(name, slow));
+        let started = Instant::now();
+        let root = on_event_loop(async move { run_probes_once(&probes, &config("{}")).await });
+        assert!(
+            started.elapsed() < Duration::from_millis(900),
+            "{:?}",
+            started.elapsed()
+        );
+        assert_eq!(root.status, StatusColor::Green);
+        assert_eq!(root.subservices.len(), 4);
+    }
+
+    #[test]
+    fn hung_probes_time_out_without_holding_up_the_rest() {
+        let hung = probe(
+            "hung",
+            "import asyncio\ncancelled = False\nasync def check():\n    global cancelled\n    try:\n        await asyncio.sleep(10**9)\n    finally:\n        cancelled = True",
+        );
+        let quick = probe("quick", "async def check():\n    return 'GREEN'");
+        let probes = [hung.clone(), quick];
+        let config = config("{'probe_timeout_seconds': 0.1}");
+        let started = Instant::now();
+        let root = on_event_loop(async move { run_probes_once(&probes, &config).await });
+        assert!(
+            started.elapsed() < Duration::from_millis(800),
+            "{:?}",
+            started.elapsed()
+        );
+        assert_eq!(
+            root.flatten()[1..],
+            [
+                (
+                    "hung".into(),
+                    StatusColor::Red,
+                    Some("health check timed out after 0.1s".into())
+                ),
+                ("quick".into(), StatusColor::Green, None),
+            ]
+        );
+        // The Python task is cancelled on its loop rather than left hanging.
+        let cancelled = || {
+            Python::with_gil(|py| {
+                hung.target
+                    .getattr(py, "__globals__")?
+                    .as_ref(py)
+                    .get_item("cancelled")?
+                    .is_true()
+            })
+            .unwrap()
+        };
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while !cancelled() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(cancelled());
+    }
+
+    #[test]
+    fn red_probes_recover_through_orange() {
+        use StatusColor::*;
+        let probe = probe("api", "def check():\n    return 'GREEN'");
+        let config = config("{'success_threshold': 3}");
+        let mut state = ProbeState::default();
+        let reported: Vec<_> = [Green, Red, Green, Green, Red, Green, Green, Green]
+            .into_iter()
+            .map(|color| {
+                let polled = ServiceStatus {
+                    status: color,
+                    description: None,
+                    ..not_answered(&probe, None)
+                };
+                state.record(Polled::Read(polled), &probe, &config, Utc::now());
+                let last = state.last.as_ref().unwrap();
+                (last.status, last.description.clone())
+            })
+            .collect();
+        let recovering = |n: u32| (Orange, Some(format!("recovering {n}/3")));
+        assert_eq!(
+            reported,
+            [
+                (Green, None),
+                (Red, None),
+                recovering(1),
+                recovering(2),
+                (Red, None),
+                recovering(1),
+                recovering(2),
+                (Green, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn non_critical_services_cap_the_root_at_orange() {
+        use StatusColor::*;
+        let leaf = |name: &str, color, critical| ServiceStatus {
+            name: name.into(),
+            status: color,
+            description: None,
+            critical,
+            ..not_answered(&probe(name, "def check():\n    pass"), None)
+        };
+        let mut optional = Arc::into_inner(probe("optional", "def check():\n    pass")).unwrap();
+        optional.critical = false;
+        let optional = Arc::new(optional);
+        // (non_critical_orange_degrades, critical color, non-critical color,
+        // whether the non-critical one is marked by its probe, root color)
+        let cases = [
+            (false, Green, Red, false, Orange),
+            (false, Green, Red, true, Orange),
+            (false, Green, Orange, false, Green),
+            (true, Green, Orange, false, Orange),
+            (false, Red, Green, false, Red),
+            (false, Orange, Red, false, Orange),
+            (false, Green, Unknown, false, Orange),
+        ];
+        for (orange_degrades, critical, non_critical, by_probe, expected) in cases {
+            let options = format!(
+                "{{'non_critical_orange_degrades': {}}}",
+                if orange_degrades { "True" } else { "False" }
+            );
+            let config = config(&options);
+            let entries = vec![
+                (None, leaf("db", critical, true)),
+                match by_probe {
+                    true => (Some(&optional), leaf("optional", non_critical, true)),
+                    false => (None, leaf("optional", non_critical, false)),
+                },
+            ];
+            let (root, children) = aggregate_entries(&config, None, entries, &Silenced::new());
+            let case = (orange_degrades, critical, non_critical, by_probe);
+            assert_eq!(root, expected, "{case:?}");
+            assert_eq!(children[1].status, non_critical, "{case:?}");
+        }
+    }
+
+    #[test]
+    fn non_critical_descendants_count_below_the_root() {
+        let mut group = ServiceStatus {
+            status: StatusColor::Green,
+            description: None,
+            ..not_answered(&probe("storage", "def check():\n    pass"), None)
+        };
+        let mut cache = group.clone();
+        cache.name = "cache".into();
+        cache.status = StatusColor::Red;
+        cache.critical = false;
+        group.subservices.push(cache);
+        let (root, _) =
+            aggregate_entries(&config("{}"), None, vec![(None, group)], &Silenced::new());
+        assert_eq!(root, StatusColor::Orange);
+    }
+
+    #[test]
+    fn long_interval_probes_leave_the_tree_fresh() {
+        let mut nightly =
+            Arc::into_inner(probe("nightly", "async def check():\n    return 'GREEN'")).unwrap();
+        nightly.interval = Some(Duration::from_secs(60));
+        let initial = not_answered(&probe("nightly", "def check():\n    pass"), None);
+        let probes = ProbeRegistry::default();
+        probes.add(nightly).unwrap();
+        // Stale after 0.3s, while the only probe is next due in a minute.
+        let config = config("{'interval_seconds': 0.1}");
+        let (shutdown, stopping) = watch::channel(false);
+        let control = ProbeControl::new(
+            shutdown,
+            probes,
+            SharedTree::new(initial),
+            &config,
+            Arc::default(),
+            None,
+        );
+        let polling = polling_task(
+            control.probes.clone(),
+            control.pushed.clone(),
+            control.heartbeats.clone(),
+            control.silences.clone(),
+            control.tree.clone(),
+            control.refreshes.clone(),
+            control.events.clone(),
+            stopping,
+            config,
+            0,
+        );
+        on_event_loop(async move {
+            let _ = tokio::time::timeout(Duration::from_secs(1), polling).await;
+        });
+        let root = control.status();
+        assert_eq!(root.status, StatusColor::Green, "{:?}", root.description);
+    }
+
+    #[test]
+    fn default_aggregate_agrees_with_the_builtin_aggregation() {
+        use StatusColor::*;
+        let leaf = |name: &str, color, critical| ServiceStatus {
+            name: name.into(),
+            status: color,
+            description: None,
+            critical,
+            ..not_answered(&probe(name, "def check():\n    pass"), None)
+        };
+        let mut storage = leaf("storage", Green, true);
+        storage.subservices.push(leaf("cache", Red, false));
+        let mut auth = leaf("auth", Green, true);
+        auth.subservices.push(leaf("tokens", Red, true));
+        let statuses = [
+            leaf("db", Green, true),
+            storage,
+            auth,
+            leaf("search", Orange, false),
+        ];
+        let default_aggregate: PyObject =
+            Python::with_gil(|py| wrap_pyfunction!(default_aggregate, py).unwrap().into());
+        let options = [
+            "{}",
+            "{'non_critical_orange_degrades': True}",
+            "{'aggregation': ('quorum', 2)}",
+            "{'aggregation': ('percentage', 0.9, 0.5)}",
+        ];
+        for options in options {
+            for silenced in [Silenced::new(), Silenced::from([("auth".into(), None)])] {
+                let entries = || statuses.iter().map(|s| (None, s.clone())).collect();
+                let builtin = config(options);
+                let (expected, _) = aggregate_entries(&builtin, None, entries(), &silenced);
+                let composed = ProbeConfig {
+                    aggregator: Some(default_aggregate.clone()),
+                    ..builtin
+                };
+                let (root, _) = aggregate_entries(&composed, None, entries(), &silenced);
+                assert_eq!(root, expected, "{options} {silenced:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn red_grandchildren_turn_the_root_red() {
+        let node = |name: &str, color| ServiceStatus {
+            status: color,
+            description: None,
+            ..not_answered(&probe(name, "def check():\n    pass"), None)
+        };
+        let mut external_api = node("external-api", StatusColor::Green);
+        external_api
+            .subservices
+            .push(node("auth", StatusColor::Red));
+        let entries = vec![
+            (None, node("database", StatusColor::Green)),
+            (None, external_api),
+        ];
+        let root = build_tree(
+            &config("{}"),
+            entries,
+            &mut HashSet::new(),
+            &Silenced::new(),
+        );
+        assert_eq!(root.status, StatusColor::Red);
+        assert_eq!(root.subservices[1].status, StatusColor::Green);
+    }
+}
@@ -0,0 +1,767 @@
+use crate::handle::instant_after;
+use crate::types::{labels_from_py, ServiceStatus, StatusColor};
+use chrono::{DateTime, Utc};
+use croner::Cron;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyString, PyTuple};
+use std::collections::{HashMap, HashSet};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, SystemTime};
+use tokio::{
+    sync::{watch, Notify},
+    time::Instant,
+};
+
+static NEXT_PROBE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Validates a user-supplied number of seconds.
+pub fn seconds_to_duration(arg: &str, seconds: f64) -> PyResult<Duration> {
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err(PyValueError::new_err(format!(
+            "{arg} must be a positive number, got {seconds}"
+        )));
+    }
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| PyValueError::new_err(format!("{arg} is too large, got {seconds}")))
+}
+
+/// Parses a five-field cron expression, e.g. `*/5 * * * *`, so that a
+/// mistake fails when the probe is declared rather than when it is due.
+pub fn parse_schedule(expr: &str) -> PyResult<Cron> {
+    Cron::new(expr)
+        .parse()
+        .map_err(|e| PyValueError::new_err(format!("invalid schedule {expr:?}: {e}")))
+}
+
+/// Rejects a streak threshold of 0: the first poll of a streak already
+/// counts.
+pub fn check_threshold(arg: &str, threshold: u32) -> PyResult<u32> {
+    if threshold == 0 {
+        return Err(PyValueError::new_err(format!("{arg} must be at least 1")));
+    }
+    Ok(threshold)
+}
+
+/// How a probe's target is invoked on every poll.
+#[derive(Clone, Copy)]
+pub enum ProbeCall {
+    /// `target.health()`
+    Method,
+    /// `target()`
+    Direct,
+}
+
+impl ProbeCall {
+    /// Objects exposing `health()` are called through it; any other callable
+    /// (e.g. an `async def` function) is called directly.
+    pub fn detect(target: &PyAny) -> PyResult<Self> {
+        if target.hasattr("health")? {
+            Ok(Self::Method)
+        } else if target.is_callable() {
+            Ok(Self::Direct)
+        } else {
+            Err(PyTypeError::new_err(format!(
+                "probe {} is neither callable nor has a health() method",
+                target.repr()?
+            )))
+        }
+    }
+}
+
+/// Longest name taken from a target's `repr()`.
+const MAX_REPR_NAME: usize = 64;
+
+/// Names a target by its string `name` attribute, else its `__name__` (for
+/// functions), else its type name, else its truncated `repr()`. The flag
+/// tells whether the name came from a `name` attribute.
+fn derive_name(target: &PyAny) -> (String, bool) {
+    for attr in ["name", "__name__"] {
+        if let Ok(name) = target.getattr(attr).and_then(|n| n.extract::<String>()) {
+            return (name, attr == "name");
+        }
+    }
+    if let Ok(name) = target.get_type().name() {
+        return (name.to_owned(), false);
+    }
+    let name = match target.repr() {
+        Ok(repr) => repr.to_string_lossy().chars().take(MAX_REPR_NAME).collect(),
+        Err(_) => String::from("probe"),
+    };
+    (name, false)
+}
+
+/// A registered health check and the name it can be removed by.
+pub struct Probe {
+    /// Unique per registration, so the poller can key state by it.
+    pub id: u64,
+    /// Also names results that omit one.
+    pub name: String,
+    /// Whether `name` was given explicitly or through a `name` attribute,
+    /// and so is expected to be unique; derived names often are not.
+    pub declared: bool,
+    pub target: PyObject,
+    pub call: ProbeCall,
+    /// Non-critical probes can only degrade the root to Orange.
+    pub critical: bool,
+    /// Overrides the global polling interval.
+    pub interval: Option<Duration>,
+    /// Cron expression, in UTC, the probe is polled on instead of an
+    /// interval.
+    pub schedule: Option<Cron>,
+    pub timeout: Option<Duration>,
+    /// Overrides the global number of retries after a failed attempt.
+    pub retries: Option<u32>,
+    /// Overrides the global number of Red polls in a row reported as such.
+    pub failure_threshold: Option<u32>,
+    /// Overrides the global number of Green polls in a row after which a
+    /// Red probe is reported Green again.
+    pub success_threshold: Option<u32>,
+    /// Labels added to every result, unless the result sets them itself.
+    pub tags: HashMap<String, String>,
+    /// Intermediate node the result is published under, if any.
+    pub group: Option<String>,
+}
+
+impl Probe {
+    /// Builds a probe, defaulting the name to one derived from the target.
+    pub fn new(target: &PyAny, name: Option<String>) -> PyResult<Self> {
+        let call = ProbeCall::detect(target)?;
+        let (name, declared) = match name {
+            Some(name) => (name, true),
+            None => derive_name(target),
+        };
+        Ok(Self {
+            id: NEXT_PROBE_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            declared,
+            target: target.into(),
+            call,
+            critical: true,
+            interval: None,
+            schedule: None,
+            timeout: None,
+            retries: None,
+            failure_threshold: None,
+            success_threshold: None,
+            tags: HashMap::new(),
+            group: None,
+        })
+    }
+
+    pub fn from_spec(py: Python<'_>, spec: &ProbeSpec) -> PyResult<Self> {
+        let mut probe = Self::new(spec.target.as_ref(py), spec.name.clone())?;
+        probe.critical = spec.critical;
+        probe.interval = spec.interval;
+        probe.schedule = spec.schedule.clone();
+        probe.timeout = spec.timeout;
+        probe.retries = spec.retries;
+        probe.failure_threshold = spec.failure_threshold;
+        probe.success_threshold = spec.success_threshold;
+        probe.tags = spec.tags.clone();
+        Ok(probe)
+    }
+
+    /// When the probe is next due after `now` according to its schedule, if
+    /// it has one that ever fires again.
+    pub fn next_scheduled(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule
+            .as_ref()?
+            .find_next_occurrence(&now, false)
+            .ok()
+    }
+
+    /// The probe's path below the root, e.g. `storage.db`.
+    pub fn path(&self) -> String {
+        match &self.group {
+            Some(group) => format!("{group}.{}", self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Invokes the target and returns whatever it produced (normally a
+    /// coroutine).
+    pub fn invoke<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let target = self.target.clone_ref(py).into_ref(py);
+        match self.call {
+            ProbeCall::Method => target.call_method0("health"),
+            ProbeCall::Direct => target.call0(),
+        }
+    }
+}
+
+/// A probe target with its own scheduling characteristics. Can be passed to
+/// `set_probe` alongside plain probe objects, which use the global defaults.
+#[pyclass]
+pub struct ProbeSpec {
+    #[pyo3(get)]
+    target: PyObject,
+    #[pyo3(get)]
+    name: Option<String>,
+    interval: Option<Duration>,
+    schedule: Option<Cron>,
+    timeout: Option<Duration>,
+    #[pyo3(get)]
+    critical: bool,
+    #[pyo3(get)]
+    retries: Option<u32>,
+    #[pyo3(get)]
+    failure_threshold: Option<u32>,
+    #[pyo3(get)]
+    success_threshold: Option<u32>,
+    #[pyo3(get)]
+    tags: HashMap<String, String>,
+}
+
+#[pymethods]
+impl ProbeSpec {
+    #[new]
+    #[pyo3(signature = (
+        target,
+        name = None,
+        interval_seconds = None,
+        timeout_seconds = None,
+        critical = true,
+        retries = None,
+        tags = None,
+        failure_threshold = None,
+        success_threshold = None,
+        schedule = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        target: PyObject,
+        name: Option<String>,
+        interval_seconds: Option<f64>,
+        timeout_seconds: Option<f64>,
+        critical: bool,
+        retries: Option<u32>,
+        tags: Option<&PyAny>,
+        failure_threshold: Option<u32>,
+        success_threshold: Option<u32>,
+        schedule: Option<&str>,
+    ) -> PyResult<Self> {
+        if interval_seconds.is_some() && schedule.is_some() {
+            return Err(PyValueError::new_err(
+                "interval_seconds and schedule cannot both be set",
+            ));
+        }
+        Ok(Self {
+            target,
+            name,
+            interval: interval_seconds
+                .map(|s| seconds_to_duration("interval_seconds", s))
+                .transpose()?,
+            schedule: schedule.map(parse_schedule).transpose()?,
+            timeout: timeout_seconds
+                .map(|s| seconds_to_duration("timeout_seconds", s))
+                .transpose()?,
+            critical,
+            retries,
+            failure_threshold: failure_threshold
+                .map(|t| check_threshold("failure_threshold", t))
+                .transpose()?,
+            success_threshold: success_threshold
+                .map(|t| check_threshold("success_threshold", t))
+                .transpose()?,
+            tags: tags
+                .map(|t| labels_from_py("tags", t))
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+
+    #[getter]
+    fn interval_seconds(&self) -> Option<f64> {
+        self.interval.map(|d| d.as_secs_f64())
+    }
+
+    #[getter]
+    fn schedule(&self) -> Option<&str> {
+        self.schedule.as_ref().map(Cron::as_str)
+    }
+
+    #[getter]
+    fn timeout_seconds(&self) -> Option<f64> {
+        self.timeout.map(|d| d.as_secs_f64())
+    }
+}
+
+/// Probes registered through the `@probe` decorator, used by `run()`.
+static DECORATED: Mutex<Vec<Arc<Probe>>> = Mutex::new(Vec::new());
+
+/// Decorator returned by `probe()`; registers the decorated function and
+/// hands it back unchanged.
+#[pyclass]
+pub struct ProbeDecorator {
+    name: Option<String>,
+    critical: bool,
+}
+
+#[pymethods]
+impl ProbeDecorator {
+    fn __call__(&self, func: &PyAny) -> PyResult<PyObject> {
+        let mut probe = Probe::new(func, self.name.clone())?;
+        probe.critical = self.critical;
+
+        // Like `duplicate_names`, only declared names clash; `run()` decides
+        // about the rest.
+        let mut decorated = DECORATED.lock().unwrap();
+        if probe.declared && decorated.iter().any(|p| p.declared && p.name == probe.name) {
+            return Err(PyValueError::new_err(format!(
+                "a probe named {:?} is already registered",
+                probe.name
+            )));
+        }
+        decorated.push(Arc::new(probe));
+        Ok(func.into())
+    }
+}
+
+/// `@probe(name=None, critical=True)` registers a health check for `run()`.
+#[pyfunction]
+#[pyo3(signature = (name = None, critical = true))]
+pub fn probe(name: Option<String>, critical: bool) -> ProbeDecorator {
+    ProbeDecorator { name, critical }
+}
+
+/// The paths of declared names appearing more than once among `probes`.
+fn duplicate_names<'a>(probes: impl IntoIterator<Item = &'a Probe>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut duplicates = Vec::new();
+    for probe in probes.into_iter().filter(|p| p.declared) {
+        let path = probe.path();
+        if !seen.insert(path.clone()) && !duplicates.contains(&path) {
+            duplicates.push(path);
+        }
+    }
+    duplicates
+}
+
+fn duplicates_error(duplicates: &[String]) -> PyErr {
+    PyValueError::new_err(format!(
+        "duplicate probe names: {}; pass allow_duplicates=True to keep them",
+        duplicates.join(", ")
+    ))
+}
+
+/// The live set of probes, shared between the polling task and the
+/// `add_probe` / `remove_probe` functions. The poller snapshots it once per
+/// cycle, so changes take effect on the next poll.
+///
+/// Unless `allow_duplicates` is set, declared names must be unique.
+#[derive(Clone, Default)]
+pub struct ProbeRegistry {
+    probes: Arc<Mutex<Vec<Arc<Probe>>>>,
+    allow_duplicates: bool,
+}
+
+impl ProbeRegistry {
+    /// Accepts a `dict[str, callable]` or a list mixing probe objects, bare
+    /// callables, `ProbeSpec`s and `(name, callable)` tuples. A dict value
+    /// may also be such a list, published as a group node named after its
+    /// key. `None` selects the probes registered with the `@probe` decorator.
+    pub fn from_py(services: Option<&PyAny>, allow_duplicates: bool) -> PyResult<Self> {
+        let probes = match services {
+            None => DECORATED.lock().unwrap().clone(),
+            Some(services) => Self::probes_from_py(services)?
+                .into_iter()
+                .map(Arc::new)
+                .collect(),
+        };
+        if !allow_duplicates {
+            let duplicates = duplicate_names(probes.iter().map(|p| &**p));
+            if !duplicates.is_empty() {
+                return Err(duplicates_error(&duplicates));
+            }
+        }
+        Ok(Self {
+            probes: Arc::new(Mutex::new(probes)),
+            allow_duplicates,
+        })
+    }
+
+    fn probes_from_py(services: &PyAny) -> PyResult<Vec<Probe>> {
+        let Ok(dict) = services.downcast::<PyDict>() else {
+            return Self::probes_from_list(services);
+        };
+        let mut probes = Vec::new();
+        for (key, value) in dict {
+            let key: String = key.extract()?;
+            if value.is_instance_of::<PyList>() {
+                for mut probe in Self::probes_from_list(value)? {
+                    probe.group = Some(key.clone());
+                    probes.push(probe);
+                }
+            } else {
+                probes.push(Probe::new(value, Some(key))?);
+            }
+        }
+        Ok(probes)
+    }
+
+    fn probes_from_list(services: &PyAny) -> PyResult<Vec<Probe>> {
+        let mut probes = Vec::new();
+        for entry in services.iter()? {
+            let entry = entry?;
+            let probe = match entry.downcast::<PyTuple>() {
+                Ok(pair) if pair.len() == 2 && pair.get_item(0)?.is_instance_of::<PyString>() => {
+                    Probe::new(pair.get_item(1)?, Some(pair.get_item(0)?.extract()?))?
+                }
+                _ => match entry.extract::<PyRef<ProbeSpec>>() {
+                    Ok(spec) => Probe::from_spec(entry.py(), &spec)?,
+                    Err(_) => Probe::new(entry, None)?,
+                },
+            };
+            probes.push(probe);
+        }
+        Ok(probes)
+    }
+
+    pub fn snapshot(&self) -> Vec<Arc<Probe>> {
+        self.probes.lock().unwrap().clone()
+    }
+
+    /// Appends `probe`, rejecting a declared name that is already taken.
+    pub fn add(&self, probe: Probe) -> PyResult<()> {
+        let mut probes = self.probes.lock().unwrap();
+        if !self.allow_duplicates {
+            let duplicates = duplicate_names(probes.iter().map(|p| &**p).chain([&probe]));
+            if !duplicates.is_empty() {
+                return Err(duplicates_error(&duplicates));
+            }
+        }
+        probes.push(Arc::new(probe));
+        Ok(())
+    }
+
+    /// Removes every probe registered under `name`, or under the path
+    /// `group.name` for grouped probes. Returns `false` if none matched.
+    pub fn remove(&self, name: &str) -> bool {
+        let mut probes = self.probes.lock().unwrap();
+        let before = probes.len();
+        probes.retain(|p| p.name != name && p.path() != name);
+        probes.len() != before
+    }
+}
+
+/// A status reported through `push_status`, kept until replaced.
+struct Pushed {
+    status: ServiceStatus,
+    expires: Option<Instant>,
+}
+
+/// Statuses pushed from outside the polling cycle, published alongside the
+/// polled ones. Each push wakes the poller so it republishes right away.
+#[derive(Clone, Default)]
+pub struct PushedStatuses(Arc<PushedInner>);
+
+#[derive(Default)]
+struct PushedInner {
+    entries: Mutex<Vec<Pushed>>,
+    changed: Notify,
+}
+
+impl PushedStatuses {
+    /// Inserts `status`, replacing any earlier push under the same name. It
+    /// turns stale once `ttl` passes without another push.
+    pub fn push(&self, mut status: ServiceStatus, ttl: Option<Duration>) {
+        status.last_checked = Some(Utc::now());
+        let entry = Pushed {
+            expires: ttl.map(|ttl| instant_after(Instant::now(), ttl)),
+            status,
+        };
+        let mut entries = self.0.entries.lock().unwrap();
+        match entries
+            .iter_mut()
+            .find(|p| p.status.name == entry.status.name)
+        {
+            Some(existing) => *existing = entry,
+            None => entries.push(entry),
+        }
+        drop(entries);
+        self.0.changed.notify_one();
+    }
+
+    /// The statuses as of `now`, expired ones degraded to Orange, and the
+    /// next instant one of them expires.
+    pub fn current(&self, now: Instant) -> (Vec<ServiceStatus>, Option<Instant>) {
+        let entries = self.0.entries.lock().unwrap();
+        let statuses = entries
+            .iter()
+            .map(|p| match p.expires {
+                Some(expires) if expires <= now => ServiceStatus {
+                    status: StatusColor::Orange,
+                    description: Some("stale".into()),
+                    ..p.status.clone()
+                },
+                _ => p.status.clone(),
+            })
+            .collect();
+        let next_expiry = entries
+            .iter()
+            .filter_map(|p| p.expires)
+            .filter(|expires| *expires > now)
+            .min();
+        (statuses, next_expiry)
+    }
+
+    /// Completes on the next push.
+    pub async fn changed(&self) {
+        self.0.changed.notified().await
+    }
+}
+
+/// A maintenance window set through `silence`.
+struct Silence {
+    reason: Option<String>,
+    /// Wall-clock end of the window, as given by a `datetime`.
+    until: Option<SystemTime>,
+}
+
+/// Services in maintenance, keyed by path. They stay in the tree but do not
+/// count towards their parent's color. Each change wakes the poller so it
+/// republishes right away.
+#[derive(Clone, Default)]
+pub struct Silences(Arc<SilencesInner>);
+
+#[derive(Default)]
+struct SilencesInner {
+    entries: Mutex<HashMap<String, Silence>>,
+    changed: Notify,
+}
+
+impl Silences {
+    /// Silences `path`, replacing any earlier silence on it.
+    pub fn silence(&self, path: String, reason: Option<String>, until: Option<SystemTime>) {
+        let silence = Silence { reason, until };
+        self.0.entries.lock().unwrap().insert(path, silence);
+        self.0.changed.notify_one();
+    }
+
+    /// Returns `false` if `path` was not silenced.
+    pub fn unsilence(&self, path: &str) -> bool {
+        let removed = self.0.entries.lock().unwrap().remove(path).is_some();
+        self.0.changed.notify_one();
+        removed
+    }
+
+    /// The reasons of the silences in effect at `now`, keyed by path, and
+    /// the time left until the next one ends. Ended silences are dropped.
+    pub fn active(&self, now: SystemTime) -> (HashMap<String, Option<String>>, Option<Duration>) {
+        let mut entries = self.0.entries.lock().unwrap();
+        entries.retain(|_, s| s.until.is_none_or(|until| until > now));
+        let reasons = entries
+            .iter()
+            .map(|(path, s)| (path.clone(), s.reason.clone()))
+            .collect();
+        let next_end = entries
+            .values()
+            .filter_map(|s| s.until?.duration_since(now).ok())
+            .min();
+        (reasons, next_end)
+    }
+
+    /// Completes on the next change.
+    pub async fn changed(&self) {
+        self.0.changed.notified().await
+    }
+}
+
+/// A service expected to call `beat` at least once per `allowed`.
+struct Heartbeat {
+    status: ServiceStatus,
+    /// Expected interval plus grace.
+    allowed: Duration,
+    last_beat: Instant,
+}
+
+/// Services reporting in with `beat` instead of being polled, published
+/// alongside the polled ones. Overdue services turn Red. Each registration
+/// and beat wakes the poller so it republishes right away.
+#[derive(Clone, Default)]
+pub struct Heartbeats(Arc<HeartbeatsInner>);
+
+#[derive(Default)]
+struct HeartbeatsInner {
+    entries: Mutex<Vec<Heartbeat>>,
+    changed: Notify,
+}
+
+impl Heartbeats {
+    /// Registers `name`, or changes its allowance if it is already
+    /// registered. A new service counts as having beaten just now.
+    pub fn register(&self, name: String, allowed: Duration) {
+        let mut entries = self.0.entries.lock().unwrap();
+        match entries.iter_mut().find(|h| h.status.name == name) {
+            Some(existing) => existing.allowed = allowed,
+            None => entries.push(Heartbeat {
+                status: ServiceStatus {
+                    name,
+                    status: StatusColor::Green,
+                    description: Some("awaiting first heartbeat".into()),
+                    subservices: Vec::new(),
+                    labels: HashMap::new(),
+                    silenced: false,
+                    stale: false,
+                    last_checked: None,
+                    latency_ms: None,
+                    metadata: None,
+                    since: None,
+                    uptime: None,
+                    uptime_samples: None,
+                    critical: true,
+                },
+                allowed,
+                last_beat: Instant::now(),
+            }),
+        }
+        drop(entries);
+        self.0.changed.notify_one();
+    }
+
+    /// Records a beat reporting `status`. Returns `false` if `name` is not
+    /// registered.
+    pub fn beat(&self, name: &str, status: StatusColor, description: Option<String>) -> bool {
+        let mut entries = self.0.entries.lock().unwrap();
+        let Some(heartbeat) = entries.iter_mut().find(|h| h.status.name == name) else {
+            return false;
+        };
+        heartbeat.status.status = status;
+        heartbeat.status.description = description;
+        heartbeat.status.last_checked = Some(Utc::now());
+        heartbeat.last_beat = Instant::now();
+        drop(entries);
+        self.0.changed.notify_one();
+        true
+    }
+
+    /// The statuses as of `now`, overdue ones turned Red, and the next
+    /// instant one of them becomes overdue.
+    pub fn current(&self, now: Instant) -> (Vec<ServiceStatus>, Option<Instant>) {
+        let entries = self.0.entries.lock().unwrap();
+        let deadline = |h: &Heartbeat| instant_after(h.last_beat, h.allowed);
+        let statuses = entries
+            .iter()
+            .map(|h| {
+                if deadline(h) > now {
+                    return h.status.clone();
+                }
+                let quiet = now.duration_since(h.last_beat).as_secs();
+                ServiceStatus {
+                    status: StatusColor::Red,
+                    description: Some(format!("no heartbeat for {quiet}s")),
+                    ..h.status.clone()
+                }
+            })
+            .collect();
+        let next_deadline = entries
+            .iter()
+            .map(deadline)
+            .filter(|deadline| *deadline > now)
+            .min();
+        (statuses, next_deadline)
+    }
+
+    /// Completes on the next registration or beat.
+    pub async fn changed(&self) {
+        self.0.changed.notified().await
+    }
+}
+
+/// Requests to poll probes right away, made through `/health?fresh=...`.
+/// Requests made before the poller picks them up share a batch, and a probe
+/// already being polled is not polled again, so a burst of requests does
+/// not stampede the probes.
+#[derive(Clone)]
+pub struct Refreshes(Arc<RefreshesInner>);
+
+struct RefreshesInner {
+    pending: Mutex<RefreshBatch>,
+    requested: Notify,
+    /// The latest batch whose probes have all been polled and published.
+    done: watch::Sender<u64>,
+}
+
+/// Probes to poll right away: every one, or those named.
+pub struct RefreshBatch {
+    pub id: u64,
+    all: bool,
+    names: Vec<String>,
+}
+
+impl RefreshBatch {
+    fn new(id: u64) -> Self {
+        Self {
+            id,
+            all: false,
+            names: Vec::new(),
+        }
+    }
+
+    /// Whether the batch asks for `probe`, by name or by path.
+    pub fn wants(&self, probe: &Probe) -> bool {
+        self.all
+            || self
+                .names
+                .iter()
+                .any(|n| *n == probe.name || *n == probe.path())
+    }
+}
+
+impl Default for Refreshes {
+    fn default() -> Self {
+        Self(Arc::new(RefreshesInner {
+            pending: Mutex::new(RefreshBatch::new(1)),
+            requested: Notify::new(),
+            done: watch::channel(0).0,
+        }))
+    }
+}
+
+impl Refreshes {
+    /// Asks for the probe named `name`, or every probe, to be polled right
+    /// away, and waits up to `timeout` for the tree to be published with
+    /// the result. Returns whether it was.
+    pub async fn refresh(&self, name: Option<&str>, timeout: Duration) -> bool {
+        let batch = {
+            let mut pending = self.0.pending.lock().unwrap();
+            match name {
+                None => pending.all = true,
+                Some(name) if !pending.names.iter().any(|n| n == name) => {
+                    pending.names.push(name.to_owned())
+                }
+                Some(_) => {}
+            }
+            pending.id
+        };
+        let mut done = self.0.done.subscribe();
+        self.0.requested.notify_one();
+        let finished = tokio::time::timeout(timeout, done.wait_for(|done| *done >= batch)).await;
+        matches!(finished, Ok(Ok(_)))
+    }
+
+    /// The batch gathered so far, if anything was asked for, starting the
+    /// next one.
+    pub fn take(&self) -> Option<RefreshBatch> {
+        let mut pending = self.0.pending.lock().unwrap();
+        if !pending.all && pending.names.is_empty() {
+            return None;
+        }
+        let next = RefreshBatch::new(pending.id + 1);
+        Some(std::mem::replace(&mut *pending, next))
+    }
+
+    /// Wakes those waiting on batch `id`, and on every one before it.
+    pub fn finish(&self, id: u64) {
+        self.0.done.send_replace(id);
+    }
+
+    /// Completes on the next request.
+    pub async fn requested(&self) {
+        self.0.requested.notified().await
+    }
+}
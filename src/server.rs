@@ -1,31 +1,143 @@
-use crate::types::{ServiceStatus, StatusColor};
+use crate::events::{module_events, Events};
+use crate::handle::{get_status, instant_after, set_active, ProbeControl, ProbeHandle, SharedTree};
+use crate::poller::{aggregate, log_py_err, poller_restarted, polling_task, run_probes_once};
+use crate::probes::{check_threshold, seconds_to_duration, ProbeRegistry};
+use crate::signals;
+use crate::types::{
+    color_from_py, labels_from_py, Limits, ServiceStatus, StatusColor, MIN_DESCRIPTION_BYTES,
+};
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
     response::{Html, IntoResponse, Json},
     routing::get,
     Router,
 };
-use pyo3::exceptions::PyRuntimeError;
+use pyo3::exceptions::{PyOSError, PyRuntimeError, PyTypeError, PyValueError};
 use pyo3::prelude::*;
-use pyo3_asyncio::tokio::into_future;
-use std::{sync::Arc, time::Duration};
-use tokio::{net::TcpListener, sync::RwLock, task::JoinHandle};
-use tracing::{error, info, Level};
-use tracing_subscriber::FmtSubscriber;
+use pyo3::types::{PyDict, PyTuple};
+use pyo3_asyncio::TaskLocals;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::{
+    net::TcpListener,
+    sync::{watch, Semaphore},
+    task::{JoinHandle, JoinSet},
+    time::Instant,
+};
+use tracing::{debug, error, info};
+use tracing_subscriber::{
+    filter::{EnvFilter, LevelFilter},
+    fmt,
+    prelude::*,
+    reload, Registry,
+};
 
 #[derive(Clone)]
 pub struct AppState {
-    pub health_tree: Arc<RwLock<ServiceStatus>>,
+    pub control: Arc<ProbeControl>,
+    pub dashboard: Arc<str>,
+    pub palette: Palette,
+    /// How long `/health?fresh=...` waits for the probes it polls.
+    pub fresh_timeout: Duration,
+}
+
+/// `/health`. With `?fresh=true`, every probe is polled first, or with
+/// `?fresh=<name>` just that one, for up to `fresh_timeout`; past that, the
+/// current tree is served with an `X-Refresh: incomplete` header.
+pub async fn get_health(
+    State(state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    let name = match query.get("fresh").map(String::as_str) {
+        None | Some("false") => None,
+        Some("true") => Some(None),
+        Some(name) => Some(Some(name)),
+    };
+    if let Some(name) = name {
+        if !state
+            .control
+            .refreshes
+            .refresh(name, state.fresh_timeout)
+            .await
+        {
+            headers.insert("x-refresh", HeaderValue::from_static("incomplete"));
+        }
+    }
+    (StatusCode::OK, headers, Json(state.control.status()))
+}
+
+/// The `/health` response of the most recently started server as
+/// `(json_body, status_code)`, for serving it from another web framework.
+#[pyfunction]
+pub fn render_health() -> PyResult<(String, u16)> {
+    let body = serde_json::to_string(&get_status()?)
+        .map_err(|e| PyRuntimeError::new_err(format!("failed to serialize health tree: {e}")))?;
+    Ok((body, StatusCode::OK.as_u16()))
+}
+
+/// `/config.json`: the dashboard's settings, for other frontends.
+pub async fn get_config(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({ "colors": state.palette }))
+}
+
+/// Dashboard color of each status, as `#rgb` or `#rrggbb`.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct Palette {
+    pub green: String,
+    pub orange: String,
+    pub red: String,
+    pub unknown: String,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            green: "#4caf50".into(),
+            orange: "#ff9800".into(),
+            red: "#f44336".into(),
+            unknown: "#9e9e9e".into(),
+        }
+    }
+}
+
+impl Palette {
+    /// Overrides the defaults with a `{status name: hex color}` dict.
+    pub fn from_py(colors: Option<&PyAny>) -> PyResult<Self> {
+        let mut palette = Self::default();
+        let Some(colors) = colors else {
+            return Ok(palette);
+        };
+        for (status, hex) in labels_from_py("colors", colors)? {
+            let slot = match status.parse::<StatusColor>()? {
+                StatusColor::Green => &mut palette.green,
+                StatusColor::Orange => &mut palette.orange,
+                StatusColor::Red => &mut palette.red,
+                StatusColor::Unknown => &mut palette.unknown,
+            };
+            *slot = check_hex_color(&status, hex)?;
+        }
+        Ok(palette)
+    }
 }
 
-pub async fn get_health(State(state): State<AppState>) -> impl IntoResponse {
-    let tree = state.health_tree.read().await;
-    (StatusCode::OK, Json(tree.clone()))
+fn check_hex_color(status: &str, hex: String) -> PyResult<String> {
+    let digits = hex.strip_prefix('#').unwrap_or_default();
+    if !matches!(digits.len(), 3 | 6) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(PyValueError::new_err(format!(
+            "colors[{status:?}] must be a hex color like \"#0a0\" or \"#00aa00\", got {hex:?}"
+        )));
+    }
+    Ok(hex)
 }
 
 const DASHBOARD_HTML: &str = r###"<!DOCTYPE html><html><head>
-<meta charset="utf-8"><title>Medic Dashboard</title>
+<meta charset="utf-8"><title>{{root_name}} Dashboard</title>
 <script src="https://d3js.org/d3.v7.min.js"></script>
 <style>
 html,body{margin:0;height:100%;font-family:sans-serif;background:#111;color:#eee}
@@ -34,15 +146,16 @@ header{padding:8px 16px;font-size:24px;font-weight:600}
 #chart{flex:1}
 #history{height:200px}
 </style></head><body>
-<header>🚑 Medic Status</header>
+<header>🚑 {{root_name}} Status</header>
 <div id="wrap">
   <div id="chart"></div>
   <div id="history"></div>
 </div>
 <script>
-const endpoint="/health", poll=3000, history=[], maxPts=120;
-function color(c){return c==="GREEN"?"#4caf50":c==="ORANGE"?"#ff9800":"#f44336";}
-function statusVal(c){return c==="GREEN"?2:c==="ORANGE"?1:0;}
+const endpoint="{{path_prefix}}/health", poll=3000, history=[], maxPts=120;
+const palette={{palette}};
+function color(c){return palette[c]||palette.RED;}
+function statusVal(c){return c==="GREEN"?2:c==="ORANGE"||c==="UNKNOWN"?1:0;}
 function drawTreemap(data){
  const root=d3.hierarchy(data,d=>d.subservices).sum(()=>1);
  const w=document.getElementById("chart").clientWidth,
@@ -84,104 +197,1185 @@ async function tick(){
 tick();setInterval(tick,poll);
 </script></body></html>"###;
 
-pub async fn get_dashboard() -> Html<&'static str> {
-    Html(DASHBOARD_HTML)
+fn escape_html(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// `path_prefix` and the palette go into the script unescaped;
+/// `check_path_prefix` and `check_hex_color` only let through characters
+/// that are safe there.
+pub fn render_dashboard(root_name: &str, path_prefix: &str, palette: &Palette) -> String {
+    let palette = serde_json::to_string(palette).expect("palette serializes");
+    DASHBOARD_HTML
+        .replace("{{root_name}}", &escape_html(root_name))
+        .replace("{{path_prefix}}", path_prefix)
+        .replace("{{palette}}", &palette)
 }
 
-fn log_py_err(msg: &str, err: PyErr) {
-    Python::with_gil(|py| {
-        error!("{msg}: {:?}", err);
-        err.print(py);
-    });
+pub async fn get_dashboard(State(state): State<AppState>) -> Html<String> {
+    Html(state.dashboard.to_string())
+}
+
+/// Description given to the aggregated root.
+#[derive(Clone)]
+pub enum RootDescription {
+    Static(String),
+    /// Called with the subservices on every cycle.
+    Dynamic(PyObject),
+}
+
+impl RootDescription {
+    pub fn from_py(obj: &PyAny) -> PyResult<Self> {
+        if let Ok(text) = obj.extract::<String>() {
+            Ok(Self::Static(text))
+        } else if obj.is_callable() {
+            Ok(Self::Dynamic(obj.into()))
+        } else {
+            Err(PyTypeError::new_err(format!(
+                "root_description must be a string or a callable, got {}",
+                obj.repr()?
+            )))
+        }
+    }
+}
+
+/// How a node's color is rolled up from its children's, unless a custom
+/// `aggregator` is set.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "policy", rename_all = "lowercase")]
+pub enum Aggregation {
+    /// The worst color, with Unknown counting as Orange.
+    Worst,
+    /// Green when at least `min_green` children are, else Red.
+    Quorum { min_green: usize },
+    /// Green when at least a `green` share of the children are Green,
+    /// Orange when at least an `orange` share are, else Red.
+    Percentage { green: f64, orange: f64 },
 }
 
-pub async fn polling_task(
-    py_services: Vec<PyObject>,
-    tree: Arc<RwLock<ServiceStatus>>,
-    interval: Duration,
-) {
-    loop {
-        let mut sub_statuses = Vec::with_capacity(py_services.len());
-
-        for obj in &py_services {
-            let fut_res: PyResult<_> = Python::with_gil(|py| {
-                let coro = obj.as_ref(py).call_method0("health")?;
-                into_future(coro)
-            });
-
-            match fut_res {
-                Ok(fut) => match fut.await {
-                    Ok(result) => {
-                        match Python::with_gil(|py| ServiceStatus::try_from(result.as_ref(py))) {
-                            Ok(status) => sub_statuses.push(status),
-                            Err(e) => log_py_err("extract ServiceStatus failed", e),
-                        }
-                    }
-                    Err(e) => log_py_err("health() raised", e),
-                },
-                Err(e) => log_py_err("into_future() failed", e),
+impl Aggregation {
+    /// Reads `"worst"`, `("quorum", min_green)` or
+    /// `("percentage", green, orange)`, shares being between 0 and 1.
+    pub fn from_py(obj: &PyAny) -> PyResult<Self> {
+        let invalid = || {
+            PyValueError::new_err(format!(
+                "aggregation must be \"worst\", (\"quorum\", min_green) or \
+                 (\"percentage\", green, orange), got {}",
+                obj.repr().map_or_else(|_| "?".into(), |r| r.to_string())
+            ))
+        };
+        if let Ok(policy) = obj.extract::<&str>() {
+            return match policy {
+                "worst" => Ok(Self::Worst),
+                _ => Err(invalid()),
+            };
+        }
+        if let Ok(("quorum", min_green)) = obj.extract::<(&str, usize)>() {
+            if min_green == 0 {
+                return Err(PyValueError::new_err("a quorum must be at least 1"));
             }
+            return Ok(Self::Quorum { min_green });
         }
+        if let Ok(("percentage", green, orange)) = obj.extract::<(&str, f64, f64)>() {
+            if !(0.0..=1.0).contains(&orange) || !(orange..=1.0).contains(&green) {
+                return Err(PyValueError::new_err(format!(
+                    "percentage cutoffs must satisfy 0 <= orange <= green <= 1, \
+                     got green={green}, orange={orange}"
+                )));
+            }
+            return Ok(Self::Percentage { green, orange });
+        }
+        Err(invalid())
+    }
 
-        let global_status = if sub_statuses
-            .iter()
-            .all(|s| matches!(s.status, StatusColor::Green))
-        {
-            StatusColor::Green
-        } else if sub_statuses
+    /// The color of a node whose counted children have `colors`. No
+    /// children at all is Green.
+    pub fn apply(&self, colors: &[StatusColor]) -> StatusColor {
+        let green = colors.iter().filter(|c| **c == StatusColor::Green).count();
+        match *self {
+            Self::Worst => aggregate(colors),
+            _ if colors.is_empty() => StatusColor::Green,
+            Self::Quorum { min_green } if green >= min_green => StatusColor::Green,
+            Self::Quorum { .. } => StatusColor::Red,
+            Self::Percentage {
+                green: cutoff,
+                orange,
+            } => {
+                let share = green as f64 / colors.len() as f64;
+                if share >= cutoff {
+                    StatusColor::Green
+                } else if share >= orange {
+                    StatusColor::Orange
+                } else {
+                    StatusColor::Red
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ProbeConfig {
+    pub host: String,
+    pub port: u16,
+    pub interval: Duration,
+    pub root_name: String,
+    pub allow_sync: bool,
+    /// Applies to probes without a timeout of their own.
+    pub timeout: Option<Duration>,
+    /// How long `/health?fresh=...` waits for the probes it polls.
+    pub fresh_timeout: Duration,
+    /// How long the polls under way at shutdown get to finish before they
+    /// are cancelled.
+    pub drain_timeout: Duration,
+    /// Until when failing or missing probe results are reported as starting
+    /// up rather than Red, counted from when the probes were set.
+    pub startup_grace_until: Option<Instant>,
+    /// How many probes may be polled at once, if limited, and the permits
+    /// to do so, shared by every copy of this config.
+    pub max_concurrency: Option<(usize, Arc<Semaphore>)>,
+    /// How long a poll may run before its probe is reported Unknown, even
+    /// if its own timeout is longer.
+    pub cycle_deadline: Option<Duration>,
+    /// Extra attempts after a failure, for probes without a count of their
+    /// own.
+    pub retries: u32,
+    /// Pause between two attempts at the same probe.
+    pub retry_delay: Duration,
+    /// Replaces the built-in root aggregation when set.
+    pub aggregator: Option<PyObject>,
+    /// The built-in aggregation, reported in the root's metadata.
+    pub aggregation: Aggregation,
+    /// How long a tree that is not updated is read as is before being
+    /// reported Unknown: `stale_tree_intervals` times `interval`.
+    pub stale_tree_after: Duration,
+    /// Whether Orange on a non-critical service degrades its parent, which
+    /// Red on one always does, if only to Orange.
+    pub non_critical_orange_degrades: bool,
+    /// The root's color while there are no probes to check, Unknown unless
+    /// set.
+    pub empty_status: StatusColor,
+    pub root_description: Option<RootDescription>,
+    /// Where the dashboard is mounted; `None` leaves it out.
+    pub dashboard_path: Option<String>,
+    /// Mount point of every route, without a trailing slash; empty for `/`.
+    pub path_prefix: String,
+    /// Keeps every subservice sharing a name instead of rejecting or
+    /// dropping all but the first.
+    pub allow_duplicates: bool,
+    /// Called with the server's URL once the listener is bound.
+    pub on_start: Option<PyObject>,
+    /// Called once the server has shut down.
+    pub on_stop: Option<PyObject>,
+    /// Reads unknown status strings in probe results as Red instead of
+    /// rejecting them.
+    pub lenient_status: bool,
+    /// Rejects unknown keys in probe result dicts and names the path to the
+    /// offending element when a result cannot be read.
+    pub strict_status: bool,
+    /// How many unreadable results in a row a probe may return while its
+    /// last readable one is still shown, marked stale.
+    pub max_stale_polls: u32,
+    /// Holds off serving until every probe has answered once, or until
+    /// `startup_timeout` has elapsed, so the warm-up tree is never served.
+    pub eager_first_poll: bool,
+    /// How long the first tree waits for every probe before being published
+    /// with the answers received so far.
+    pub startup_timeout: Duration,
+    /// Spreads polls by up to this fraction of their interval either way.
+    pub jitter_fraction: f64,
+    /// Failures in a row after which a probe is polled less and less often,
+    /// if ever.
+    pub backoff_after: Option<u32>,
+    /// What the wait between polls is multiplied by on each further failure.
+    pub backoff_factor: f64,
+    /// The longest a backing-off probe waits between polls.
+    pub max_backoff: Duration,
+    /// Identical broken or unreadable results in a row after which a probe
+    /// is no longer called for a while, if ever.
+    pub circuit_breaker_after: Option<u32>,
+    /// How long a probe is no longer called for.
+    pub circuit_breaker_cooldown: Duration,
+    /// Red polls in a row before a probe is reported Red; until then it
+    /// keeps its previous color. Probes may override it.
+    pub failure_threshold: u32,
+    /// Green polls in a row before a Red probe is reported Green again;
+    /// until then it is reported Orange. Probes may override it.
+    pub success_threshold: u32,
+    /// How many of each probe's latest polls its color changes are counted
+    /// over.
+    pub flap_window: usize,
+    /// Color changes within `flap_window` beyond which a probe is reported
+    /// Orange as flapping, if ever.
+    pub flap_threshold: Option<u32>,
+    /// How long a flapping probe must keep its color to no longer be.
+    pub flap_quiet: Duration,
+    /// Dashboard colors, also served at `/config.json`.
+    pub palette: Palette,
+    /// How far back each service's `uptime` looks.
+    pub uptime_window: Duration,
+    /// Probe results beyond these are reported as unreadable, except for
+    /// long descriptions, which are truncated.
+    pub limits: Limits,
+}
+
+impl ProbeConfig {
+    /// Reads the server options out of `options`, each with the same
+    /// default and checks whichever entry point it is given to.
+    pub fn from_options(options: &Options<'_>) -> PyResult<Self> {
+        let interval = seconds_to_duration(
+            "interval_seconds",
+            options
+                .take::<Option<f64>>("interval_seconds", None)?
+                .unwrap_or(5.0),
+        )?;
+        let aggregator = options.take::<Option<&PyAny>>("aggregator", None)?;
+        let aggregation = options
+            .take::<Option<&PyAny>>("aggregation", None)?
+            .map(Aggregation::from_py)
+            .transpose()?
+            .unwrap_or(Aggregation::Worst);
+        if aggregator.is_some() && aggregation != Aggregation::Worst {
+            return Err(PyValueError::new_err(
+                "aggregator and aggregation cannot both be set",
+            ));
+        }
+        let dashboard_path = options.take("dashboard_path", String::from("/"))?;
+        Ok(Self {
+            host: options.take("host", String::from("0.0.0.0"))?,
+            port: options.take("port", 3000)?,
+            interval,
+            stale_tree_after: check_stale_tree_intervals(
+                interval,
+                options.take("stale_tree_intervals", 3.0)?,
+            )?,
+            root_name: options.take("root_name", String::from("medic"))?,
+            allow_sync: options.take("allow_sync", false)?,
+            timeout: options
+                .take("probe_timeout_seconds", Some(DEFAULT_PROBE_TIMEOUT_SECONDS))?
+                .map(|s| seconds_to_duration("probe_timeout_seconds", s))
+                .transpose()?,
+            fresh_timeout: seconds_to_duration(
+                "fresh_timeout_seconds",
+                options.take("fresh_timeout_seconds", 10.0)?,
+            )?,
+            drain_timeout: seconds_to_duration(
+                "drain_timeout_seconds",
+                options.take("drain_timeout_seconds", 5.0)?,
+            )?,
+            startup_grace_until: options
+                .take::<Option<f64>>("startup_grace_seconds", None)?
+                .map(|s| seconds_to_duration("startup_grace_seconds", s))
+                .transpose()?
+                .map(|grace| instant_after(Instant::now(), grace)),
+            retries: options.take("retries", 0)?,
+            retry_delay: seconds_to_duration(
+                "retry_delay_seconds",
+                options.take("retry_delay_seconds", 0.5)?,
+            )?,
+            cycle_deadline: options
+                .take::<Option<f64>>("cycle_deadline_seconds", None)?
+                .map(|s| seconds_to_duration("cycle_deadline_seconds", s))
+                .transpose()?,
+            max_concurrency: check_max_concurrency(options.take("max_concurrency", None)?)?
+                .map(|limit| (limit, Arc::new(Semaphore::new(limit)))),
+            aggregator: check_callable("aggregator", aggregator)?,
+            aggregation,
+            non_critical_orange_degrades: options.take("non_critical_orange_degrades", false)?,
+            empty_status: options
+                .take::<Option<&PyAny>>("empty_status", None)?
+                .map(color_from_py)
+                .transpose()?
+                .unwrap_or(StatusColor::Unknown),
+            root_description: options
+                .take::<Option<&PyAny>>("root_description", None)?
+                .map(RootDescription::from_py)
+                .transpose()?,
+            dashboard_path: options
+                .take("dashboard", true)?
+                .then_some(dashboard_path)
+                .map(check_dashboard_path)
+                .transpose()?,
+            path_prefix: check_path_prefix(&options.take::<String>("path_prefix", String::new())?)?,
+            allow_duplicates: options.take("allow_duplicates", false)?,
+            on_start: check_callable("on_start", options.take("on_start", None)?)?,
+            on_stop: check_callable("on_stop", options.take("on_stop", None)?)?,
+            lenient_status: options.take("lenient_status", false)?,
+            uptime_window: seconds_to_duration(
+                "uptime_window_seconds",
+                options.take("uptime_window_seconds", 3600.0)?,
+            )?,
+            palette: Palette::from_py(options.take("colors", None)?)?,
+            strict_status: options.take("strict_status", false)?,
+            max_stale_polls: options.take("max_stale_polls", 3)?,
+            eager_first_poll: options.take("eager_first_poll", true)?,
+            startup_timeout: seconds_to_duration(
+                "startup_timeout_seconds",
+                options.take("startup_timeout_seconds", 10.0)?,
+            )?,
+            jitter_fraction: check_jitter_fraction(options.take("jitter_fraction", 0.1)?)?,
+            backoff_after: check_failure_count(
+                "backoff_after",
+                options.take("backoff_after", None)?,
+            )?,
+            backoff_factor: check_backoff_factor(options.take("backoff_factor", 2.0)?)?,
+            max_backoff: seconds_to_duration(
+                "max_backoff_seconds",
+                options.take("max_backoff_seconds", 300.0)?,
+            )?,
+            circuit_breaker_after: check_failure_count(
+                "circuit_breaker_after",
+                options.take("circuit_breaker_after", None)?,
+            )?,
+            circuit_breaker_cooldown: seconds_to_duration(
+                "circuit_breaker_cooldown_seconds",
+                options.take("circuit_breaker_cooldown_seconds", 60.0)?,
+            )?,
+            failure_threshold: check_threshold(
+                "failure_threshold",
+                options.take("failure_threshold", 1)?,
+            )?,
+            success_threshold: check_threshold(
+                "success_threshold",
+                options.take("success_threshold", 1)?,
+            )?,
+            flap_window: check_flap_window(options.take("flap_window", 20)?)?,
+            flap_threshold: check_failure_count(
+                "flap_threshold",
+                options.take("flap_threshold", None)?,
+            )?,
+            flap_quiet: seconds_to_duration(
+                "flap_quiet_seconds",
+                options.take("flap_quiet_seconds", 60.0)?,
+            )?,
+            limits: Limits {
+                max_depth: options.take("max_depth", 32)?,
+                max_nodes: check_max_nodes(options.take("max_nodes", 10_000)?)?,
+                max_description_bytes: check_max_description_bytes(
+                    options.take("max_description_bytes", 1024)?,
+                )?,
+            },
+        })
+    }
+}
+
+/// The keyword arguments of an entry point. Each is taken out as it is
+/// read, so that `finish` can reject any the entry point does not know.
+/// Entry points spell the options they take out in their `text_signature`
+/// for `help()` and editors, to be kept in step with `from_options`.
+pub struct Options<'py> {
+    function: &'static str,
+    kwargs: &'py PyDict,
+}
+
+impl<'py> Options<'py> {
+    pub fn new(
+        py: Python<'py>,
+        function: &'static str,
+        kwargs: Option<&'py PyDict>,
+    ) -> PyResult<Self> {
+        let kwargs = match kwargs {
+            Some(kwargs) => kwargs.copy()?,
+            None => PyDict::new(py),
+        };
+        Ok(Self { function, kwargs })
+    }
+
+    /// Rejects any of `names`, which this entry point does not take even
+    /// though others do.
+    pub fn exclude(self, names: &[&str]) -> PyResult<Self> {
+        match names
             .iter()
-            .any(|s| matches!(s.status, StatusColor::Red))
+            .find(|name| self.kwargs.contains(name).unwrap_or(false))
         {
-            StatusColor::Red
-        } else {
-            StatusColor::Orange
-        };
+            Some(name) => Err(self.unexpected(name)),
+            None => Ok(self),
+        }
+    }
 
-        *tree.write().await = ServiceStatus {
-            name: "medic".into(),
-            status: global_status,
-            description: None,
-            subservices: sub_statuses,
+    /// Takes out `name` as a `T`, or `default` when it was not given.
+    pub fn take<T: FromPyObject<'py>>(&self, name: &str, default: T) -> PyResult<T> {
+        let Some(value) = self.kwargs.get_item(name)? else {
+            return Ok(default);
         };
+        self.kwargs.del_item(name)?;
+        value.extract().map_err(|e| {
+            PyTypeError::new_err(format!(
+                "{}() argument '{name}': {}",
+                self.function,
+                e.value(value.py())
+            ))
+        })
+    }
+
+    /// Rejects whatever was given but not taken.
+    pub fn finish(self) -> PyResult<()> {
+        match self.kwargs.keys().iter().next() {
+            Some(name) => Err(self.unexpected(&name.str()?.to_string_lossy())),
+            None => Ok(()),
+        }
+    }
 
-        tokio::time::sleep(interval).await;
+    fn unexpected(&self, name: &str) -> PyErr {
+        PyTypeError::new_err(format!(
+            "{}() got an unexpected keyword argument '{name}'",
+            self.function
+        ))
     }
 }
 
-#[pyfunction]
-pub fn set_probe(py: Python<'_>, services: Vec<PyObject>) -> PyResult<()> {
-    tracing::subscriber::set_global_default(
-        FmtSubscriber::builder()
-            .with_max_level(Level::INFO)
-            .finish(),
-    )
-    .map_err(|e| PyRuntimeError::new_err(format!("failed to init tracing: {e}")))?;
+/// How long a probe may take before it is reported Red, unless told
+/// otherwise.
+const DEFAULT_PROBE_TIMEOUT_SECONDS: f64 = 10.0;
+
+/// Options only meaningful with an HTTP listener.
+const SERVER_OPTIONS: &[&str] = &[
+    "host",
+    "port",
+    "dashboard",
+    "dashboard_path",
+    "path_prefix",
+    "on_start",
+    "on_stop",
+    "colors",
+    "eager_first_poll",
+    "fresh_timeout_seconds",
+];
+
+/// Options only meaningful when probes are polled over and over.
+const POLLING_OPTIONS: &[&str] = &[
+    "interval_seconds",
+    "probe_timeout_seconds",
+    "retries",
+    "retry_delay_seconds",
+    "uptime_window_seconds",
+    "max_stale_polls",
+    "startup_timeout_seconds",
+    "jitter_fraction",
+    "backoff_after",
+    "backoff_factor",
+    "max_backoff_seconds",
+    "circuit_breaker_after",
+    "circuit_breaker_cooldown_seconds",
+    "failure_threshold",
+    "success_threshold",
+    "flap_window",
+    "flap_threshold",
+    "flap_quiet_seconds",
+    "startup_grace_seconds",
+    "drain_timeout_seconds",
+    "stale_tree_intervals",
+    "log_level",
+    "log_json",
+    "log_filter",
+];
+
+/// How an entry point sets up logging; see `init_tracing`.
+#[derive(Clone)]
+pub struct Logging {
+    level: String,
+    json: bool,
+    filter: Option<String>,
+}
 
-    pyo3_asyncio::tokio::run(py, async move {
-        let tree = Arc::new(RwLock::new(ServiceStatus {
-            name: "medic".into(),
-            status: StatusColor::Orange,
-            description: Some("warming up".into()),
-            subservices: vec![],
-        }));
+impl Logging {
+    pub fn from_options(options: &Options<'_>) -> PyResult<Self> {
+        Ok(Self {
+            level: options.take("log_level", String::from("info"))?,
+            json: options.take("log_json", false)?,
+            filter: options.take("log_filter", None)?,
+        })
+    }
 
-        let task_locals = Python::with_gil(|py| pyo3_asyncio::tokio::get_current_locals(py))?;
+    pub fn init(&self) -> PyResult<()> {
+        init_tracing(&self.level, self.json, self.filter.as_deref())
+    }
+}
 
-        let _bg: JoinHandle<()> = tokio::spawn(pyo3_asyncio::tokio::scope(
-            task_locals,
-            polling_task(services, tree.clone(), Duration::from_secs(5)),
+fn check_jitter_fraction(fraction: f64) -> PyResult<f64> {
+    if !(0.0..1.0).contains(&fraction) {
+        return Err(PyValueError::new_err(format!(
+            "jitter_fraction must be at least 0 and below 1, got {fraction}"
+        )));
+    }
+    Ok(fraction)
+}
+
+fn check_failure_count(arg: &str, count: Option<u32>) -> PyResult<Option<u32>> {
+    if count == Some(0) {
+        return Err(PyValueError::new_err(format!(
+            "{arg} must be at least 1, or None to disable it"
+        )));
+    }
+    Ok(count)
+}
+
+fn check_max_concurrency(limit: Option<usize>) -> PyResult<Option<usize>> {
+    if limit == Some(0) {
+        return Err(PyValueError::new_err(
+            "max_concurrency must be at least 1, or None for no limit",
         ));
+    }
+    Ok(limit)
+}
 
-        let state = AppState { health_tree: tree };
+fn check_backoff_factor(factor: f64) -> PyResult<f64> {
+    if !factor.is_finite() || factor < 1.0 {
+        return Err(PyValueError::new_err(format!(
+            "backoff_factor must be at least 1, got {factor}"
+        )));
+    }
+    Ok(factor)
+}
 
-        let app = Router::new()
-            .route("/health", get(get_health))
-            .route("/", get(get_dashboard))
-            .with_state(state);
+/// `intervals` times `interval`, for a positive number of intervals.
+fn check_stale_tree_intervals(interval: Duration, intervals: f64) -> PyResult<Duration> {
+    if !intervals.is_finite() || intervals <= 0.0 {
+        return Err(PyValueError::new_err(format!(
+            "stale_tree_intervals must be a positive number, got {intervals}"
+        )));
+    }
+    Duration::try_from_secs_f64(interval.as_secs_f64() * intervals).map_err(|_| {
+        PyValueError::new_err(format!(
+            "stale_tree_intervals is too large, got {intervals}"
+        ))
+    })
+}
 
-        let listener = TcpListener::bind("0.0.0.0:3000").await?;
-        info!("Medic server at http://{}", listener.local_addr()?);
-        axum::serve(listener, app).await?;
-        Ok(())
+fn check_flap_window(window: usize) -> PyResult<usize> {
+    if window < 2 {
+        return Err(PyValueError::new_err(format!(
+            "flap_window must be at least 2, got {window}"
+        )));
+    }
+    Ok(window)
+}
+
+fn check_max_nodes(max_nodes: usize) -> PyResult<usize> {
+    if max_nodes == 0 {
+        return Err(PyValueError::new_err("max_nodes must be at least 1"));
+    }
+    Ok(max_nodes)
+}
+
+fn check_max_description_bytes(max_bytes: usize) -> PyResult<usize> {
+    if max_bytes < MIN_DESCRIPTION_BYTES {
+        return Err(PyValueError::new_err(format!(
+            "max_description_bytes must be at least {MIN_DESCRIPTION_BYTES}, got {max_bytes}"
+        )));
+    }
+    Ok(max_bytes)
+}
+
+fn check_callable(arg: &str, obj: Option<&PyAny>) -> PyResult<Option<PyObject>> {
+    match obj {
+        Some(obj) if !obj.is_callable() => Err(PyTypeError::new_err(format!(
+            "{arg} {} is not callable",
+            obj.repr()?
+        ))),
+        obj => Ok(obj.map(Into::into)),
+    }
+}
+
+fn check_dashboard_path(path: String) -> PyResult<String> {
+    if !path.starts_with('/') {
+        return Err(PyValueError::new_err(format!(
+            "dashboard_path must start with '/', got {path:?}"
+        )));
+    }
+    if path == "/health" {
+        return Err(PyValueError::new_err(
+            "dashboard_path cannot be /health, which serves the health tree",
+        ));
+    }
+    Ok(path)
+}
+
+/// Normalizes `path_prefix` to `/a/b` form, or empty for the root.
+fn check_path_prefix(prefix: &str) -> PyResult<String> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "/-._~".contains(c);
+    if (!prefix.is_empty() && !prefix.starts_with('/')) || !prefix.chars().all(safe) {
+        return Err(PyValueError::new_err(format!(
+            "path_prefix must be a path like /internal/medic, got {prefix:?}"
+        )));
+    }
+    Ok(prefix.trim_end_matches('/').to_owned())
+}
+
+/// Filter control of the subscriber installed by the first server started
+/// in this process.
+static TRACING: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Installs the global subscriber on first use; later calls only change the
+/// filter, so servers can be restarted within one interpreter, while the
+/// output format stays the one chosen first. `log_filter` uses `RUST_LOG`
+/// syntax, with `log_level` applying to targets it does not mention.
+/// `log_level="off"` never installs a subscriber, so the host application's
+/// own one stays in place.
+fn init_tracing(log_level: &str, log_json: bool, log_filter: Option<&str>) -> PyResult<()> {
+    let level: LevelFilter = log_level
+        .parse()
+        .map_err(|_| PyValueError::new_err(format!("unknown log_level {log_level:?}")))?;
+    let filter = log_filter.unwrap_or_default();
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .parse(filter)
+        .map_err(|e| PyValueError::new_err(format!("invalid log_filter {filter:?}: {e}")))?;
+
+    if level == LevelFilter::OFF && TRACING.get().is_none() {
+        return Ok(());
+    }
+    let mut filter = Some(filter);
+    let handle = TRACING.get_or_init(|| {
+        let (layer, handle) = reload::Layer::new(filter.take().unwrap());
+        if let Err(e) = tracing_subscriber::registry()
+            .with(layer)
+            .with(log_json.then(|| fmt::layer().json()))
+            .with((!log_json).then(fmt::layer))
+            .try_init()
+        {
+            debug!("tracing subscriber already installed: {e}");
+        }
+        handle
+    });
+    if let Some(filter) = filter {
+        // Only fails when another subscriber won, whose filter is not ours
+        // to set.
+        let _ = handle.reload(filter);
+    }
+    Ok(())
+}
+
+pub async fn bind(config: &ProbeConfig) -> PyResult<TcpListener> {
+    let (host, port) = (&config.host, config.port);
+    TcpListener::bind((host.as_str(), port))
+        .await
+        .map_err(|e| PyOSError::new_err(format!("failed to bind {host}:{port}: {e}")))
+}
+
+/// The URL serving the routes of a listener bound at `addr`. Wildcard
+/// addresses are replaced with loopback so the URL can be connected to.
+fn base_url(addr: SocketAddr, path_prefix: &str) -> String {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    format!("http://{}{path_prefix}", SocketAddr::new(ip, addr.port()))
+}
+
+/// Creates the shared state for a new server polling `probes` and
+/// publishing its transitions to `events`.
+fn new_control(
+    probes: ProbeRegistry,
+    config: &ProbeConfig,
+    events: Arc<Events>,
+    event_loop: Option<PyObject>,
+) -> (Arc<ProbeControl>, watch::Receiver<bool>) {
+    let tree = SharedTree::new(ServiceStatus {
+        name: config.root_name.clone(),
+        status: StatusColor::Unknown,
+        description: Some("warming up".into()),
+        subservices: vec![],
+        labels: HashMap::new(),
+        silenced: false,
+        stale: false,
+        last_checked: None,
+        latency_ms: None,
+        metadata: None,
+        since: None,
+        uptime: None,
+        uptime_samples: None,
+        critical: true,
+    });
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let control = ProbeControl::new(shutdown_tx, probes, tree, config, events, event_loop);
+    (control, shutdown_rx)
+}
+
+/// Creates the shared state for a new server and makes it the target of the
+/// module-level functions.
+fn register(
+    services: Option<&PyAny>,
+    config: &ProbeConfig,
+    event_loop: Option<PyObject>,
+) -> PyResult<(Arc<ProbeControl>, watch::Receiver<bool>)> {
+    let (control, shutdown_rx) = new_control(
+        ProbeRegistry::from_py(services, config.allow_duplicates)?,
+        config,
+        module_events(),
+        event_loop,
+    );
+    set_active(&control);
+    Ok((control, shutdown_rx))
+}
+
+/// How long a panicked polling task is given before it is started over.
+const POLLING_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Spawns the polling task into the current `pyo3_asyncio` task scope,
+/// under a supervisor that logs it panicking and starts it over after
+/// `POLLING_RESTART_DELAY`, with the root reported Unknown in between.
+/// Both stop once `shutdown` flips to `true` and the polls under way are
+/// drained; aborting the returned handle stops them at once.
+fn spawn_polling(
+    control: &ProbeControl,
+    config: &ProbeConfig,
+    shutdown: &watch::Receiver<bool>,
+) -> PyResult<JoinHandle<()>> {
+    let task_locals = Python::with_gil(|py| {
+        let task_locals = pyo3_asyncio::tokio::get_current_locals(py)?;
+        let _ = control.probe_loop.set(task_locals.event_loop(py).into());
+        Ok::<_, PyErr>(task_locals)
+    })?;
+    let (probes, pushed, heartbeats, silences, refreshes, tree, events) = (
+        control.probes.clone(),
+        control.pushed.clone(),
+        control.heartbeats.clone(),
+        control.silences.clone(),
+        control.refreshes.clone(),
+        control.tree.clone(),
+        control.events.clone(),
+    );
+    let config = config.clone();
+    let shutdown = shutdown.clone();
+    Ok(tokio::spawn(async move {
+        // Dropped with the supervisor, which aborts the polling task.
+        let mut polling = JoinSet::new();
+        let mut restarts = 0;
+        loop {
+            polling.spawn(pyo3_asyncio::tokio::scope(
+                task_locals.clone(),
+                polling_task(
+                    probes.clone(),
+                    pushed.clone(),
+                    heartbeats.clone(),
+                    silences.clone(),
+                    tree.clone(),
+                    refreshes.clone(),
+                    events.clone(),
+                    shutdown.clone(),
+                    config.clone(),
+                    restarts,
+                ),
+            ));
+            match polling.join_next().await {
+                Some(Err(e)) if e.is_panic() && !*shutdown.borrow() => {
+                    restarts += 1;
+                    error!("Polling task panicked, restarting it: {e}");
+                    tree.publish(poller_restarted(&tree.get(), restarts));
+                    tokio::time::sleep(POLLING_RESTART_DELAY).await;
+                }
+                _ => return,
+            }
+        }
+    }))
+}
+
+/// Calls a lifecycle hook, awaiting its result when it is awaitable. Errors
+/// are logged rather than raised, so a failing hook never prevents startup
+/// or shutdown. Must be awaited within a `pyo3_asyncio` task scope.
+async fn run_hook(name: &str, hook: &PyObject, args: impl IntoPy<Py<PyTuple>>) {
+    let called = Python::with_gil(|py| {
+        let result = hook.call1(py, args)?.into_ref(py);
+        if !result.hasattr("__await__")? {
+            return Ok(None);
+        }
+        let locals = pyo3_asyncio::tokio::get_current_locals(py)?;
+        pyo3_asyncio::into_future_with_locals(&locals, result).map(Some)
+    });
+    let finished = match called {
+        Ok(Some(fut)) => fut.await.map(drop),
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = finished {
+        log_py_err(&format!("{name} hook raised"), e);
+    }
+}
+
+/// Runs the polling task alone until `shutdown` flips to `true`. Same
+/// contract as `serve`, minus the listener.
+pub async fn poll(
+    control: Arc<ProbeControl>,
+    config: ProbeConfig,
+    mut shutdown: watch::Receiver<bool>,
+) -> PyResult<()> {
+    let bg = spawn_polling(&control, &config, &shutdown)?;
+    info!("Polling probes every {:?}", config.interval);
+    let _ = shutdown.wait_for(|stop| *stop).await;
+    let _ = bg.await;
+    drop(shutdown);
+    info!("Medic polling stopped");
+    Ok(())
+}
+
+/// Runs the polling task and the HTTP server on `listener` until `shutdown`
+/// flips to `true`. Must be awaited within a `pyo3_asyncio` task scope.
+/// With `eager_first_poll`, requests are only answered once the first tree
+/// is published.
+///
+/// `shutdown` is held until everything has stopped, so `ProbeControl` can
+/// use the channel closing as its "finished" signal.
+pub async fn serve(
+    listener: TcpListener,
+    control: Arc<ProbeControl>,
+    config: ProbeConfig,
+    shutdown: watch::Receiver<bool>,
+) -> PyResult<()> {
+    let bg = spawn_polling(&control, &config, &shutdown)?;
+
+    let state = AppState {
+        control: control.clone(),
+        dashboard: render_dashboard(&config.root_name, &config.path_prefix, &config.palette).into(),
+        palette: config.palette.clone(),
+        fresh_timeout: config.fresh_timeout,
+    };
+
+    let mut app = Router::new()
+        .route("/health", get(get_health))
+        .route("/config.json", get(get_config));
+    if let Some(path) = &config.dashboard_path {
+        app = app.route(path, get(get_dashboard));
+    }
+    let app = match config.path_prefix.as_str() {
+        "" => app,
+        prefix => Router::new().nest(prefix, app),
+    }
+    .with_state(state);
+
+    if config.eager_first_poll {
+        let mut stopping = shutdown.clone();
+        tokio::select! {
+            _ = tokio::time::timeout(config.startup_timeout, control.tree.published()) => {}
+            _ = stopping.wait_for(|stop| *stop) => {}
+        }
+    }
+    let url = base_url(listener.local_addr()?, &config.path_prefix);
+    info!("Medic server at {url}");
+    if let Some(on_start) = &config.on_start {
+        run_hook("on_start", on_start, (url,)).await;
+    }
+    info!("Polling probes every {:?}", config.interval);
+    let mut signal = shutdown.clone();
+    let served = axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = signal.wait_for(|stop| *stop).await;
+        })
+        .await;
+    // Polling drains on its own once asked to stop, but not if the listener
+    // failed instead.
+    if !*shutdown.borrow() {
+        bg.abort();
+    }
+    let _ = bg.await;
+    drop(shutdown);
+    info!("Medic server stopped");
+    if let Some(on_stop) = &config.on_stop {
+        run_hook("on_stop", on_stop, ()).await;
+    }
+    served?;
+    Ok(())
+}
+
+/// Serves the probes until stopped, blocking the calling thread. With
+/// `run_in_background`, behaves like `start_probe` and returns its handle.
+#[pyfunction]
+#[pyo3(signature = (services = None, **options))]
+#[pyo3(
+    text_signature = "(services=None, *, run_in_background=False, host='0.0.0.0', port=3000, \
+    interval_seconds=5.0, stale_tree_intervals=3.0, root_name='medic', allow_sync=False, \
+    probe_timeout_seconds=10.0, fresh_timeout_seconds=10.0, drain_timeout_seconds=5.0, \
+    startup_grace_seconds=None, retries=0, retry_delay_seconds=0.5, cycle_deadline_seconds=None, \
+    max_concurrency=None, aggregator=None, aggregation='worst', \
+    non_critical_orange_degrades=False, empty_status=None, root_description=None, dashboard=True, \
+    dashboard_path='/', path_prefix='', allow_duplicates=False, on_start=None, on_stop=None, \
+    lenient_status=False, uptime_window_seconds=3600.0, colors=None, strict_status=False, \
+    max_stale_polls=3, eager_first_poll=True, startup_timeout_seconds=10.0, jitter_fraction=0.1, \
+    backoff_after=None, backoff_factor=2.0, max_backoff_seconds=300.0, circuit_breaker_after=None, \
+    circuit_breaker_cooldown_seconds=60.0, failure_threshold=1, success_threshold=1, \
+    flap_window=20, flap_threshold=None, flap_quiet_seconds=60.0, max_depth=32, max_nodes=10000, \
+    max_description_bytes=1024, log_level='info', log_json=False, log_filter=None)"
+)]
+pub fn set_probe(
+    py: Python<'_>,
+    services: Option<&PyAny>,
+    options: Option<&PyDict>,
+) -> PyResult<Option<ProbeHandle>> {
+    let options = Options::new(py, "set_probe", options)?;
+    let run_in_background = options.take("run_in_background", false)?;
+    let logging = Logging::from_options(&options)?;
+    let config = ProbeConfig::from_options(&options)?;
+    options.finish()?;
+    logging.init()?;
+    if run_in_background {
+        let handle = start_in_background(
+            py,
+            ProbeRegistry::from_py(services, config.allow_duplicates)?,
+            config,
+            module_events(),
+        )?;
+        set_active(handle.control());
+        return Ok(Some(handle));
+    }
+
+    let (control, shutdown_rx) = register(services, &config, None)?;
+
+    // Ctrl-C and SIGTERM stop the server and return normally, so the
+    // caller's cleanup runs instead of a KeyboardInterrupt traceback.
+    let previous = signals::install(py, &control)?;
+    let served = pyo3_asyncio::tokio::run(py, async move {
+        let listener = bind(&config).await?;
+        let stopper = control.clone();
+        let on_signal = tokio::spawn(async move {
+            signals::shutdown_signal().await;
+            info!("Shutdown signal received");
+            stopper.request_stop();
+        });
+        let served = serve(listener, control, config, shutdown_rx).await;
+        on_signal.abort();
+        served
+    });
+    signals::restore(py, previous)?;
+    served.map(|()| None)
+}
+
+/// Runs `set_probe` with every probe registered through `@probe`, forwarding
+/// the keyword arguments and the result unchanged.
+#[pyfunction]
+#[pyo3(signature = (**server_kwargs))]
+#[pyo3(
+    text_signature = "(*, run_in_background=False, host='0.0.0.0', port=3000, \
+    interval_seconds=5.0, stale_tree_intervals=3.0, root_name='medic', allow_sync=False, \
+    probe_timeout_seconds=10.0, fresh_timeout_seconds=10.0, drain_timeout_seconds=5.0, \
+    startup_grace_seconds=None, retries=0, retry_delay_seconds=0.5, cycle_deadline_seconds=None, \
+    max_concurrency=None, aggregator=None, aggregation='worst', \
+    non_critical_orange_degrades=False, empty_status=None, root_description=None, dashboard=True, \
+    dashboard_path='/', path_prefix='', allow_duplicates=False, on_start=None, on_stop=None, \
+    lenient_status=False, uptime_window_seconds=3600.0, colors=None, strict_status=False, \
+    max_stale_polls=3, eager_first_poll=True, startup_timeout_seconds=10.0, jitter_fraction=0.1, \
+    backoff_after=None, backoff_factor=2.0, max_backoff_seconds=300.0, circuit_breaker_after=None, \
+    circuit_breaker_cooldown_seconds=60.0, failure_threshold=1, success_threshold=1, \
+    flap_window=20, flap_threshold=None, flap_quiet_seconds=60.0, max_depth=32, max_nodes=10000, \
+    max_description_bytes=1024, log_level='info', log_json=False, log_filter=None)"
+)]
+pub fn run(py: Python<'_>, server_kwargs: Option<&PyDict>) -> PyResult<PyObject> {
+    Ok(wrap_pyfunction!(set_probe, py)?
+        .call((), server_kwargs)?
+        .into())
+}
+
+/// Polls every probe exactly once, without binding a port, and returns the
+/// aggregated root. Probes that raise or time out become Red entries; they
+/// time out after `timeout` seconds, as with the servers' default
+/// `probe_timeout_seconds`, or never with `timeout=None`.
+#[pyfunction]
+#[pyo3(signature = (services = None, timeout = Some(DEFAULT_PROBE_TIMEOUT_SECONDS), **options))]
+#[pyo3(
+    text_signature = "(services=None, timeout=10.0, *, root_name='medic', allow_sync=False, \
+    cycle_deadline_seconds=None, max_concurrency=None, aggregator=None, aggregation='worst', \
+    non_critical_orange_degrades=False, empty_status=None, root_description=None, \
+    allow_duplicates=False, lenient_status=False, strict_status=False, max_depth=32, \
+    max_nodes=10000, max_description_bytes=1024)"
+)]
+pub fn check_once(
+    py: Python<'_>,
+    services: Option<&PyAny>,
+    timeout: Option<f64>,
+    options: Option<&PyDict>,
+) -> PyResult<ServiceStatus> {
+    let options = Options::new(py, "check_once", options)?
+        .exclude(SERVER_OPTIONS)?
+        .exclude(POLLING_OPTIONS)?;
+    let config = ProbeConfig {
+        timeout: timeout
+            .map(|s| seconds_to_duration("timeout", s))
+            .transpose()?,
+        max_stale_polls: 0,
+        eager_first_poll: false,
+        jitter_fraction: 0.0,
+        ..ProbeConfig::from_options(&options)?
+    };
+    options.finish()?;
+    let probes = ProbeRegistry::from_py(services, config.allow_duplicates)?.snapshot();
+
+    pyo3_asyncio::tokio::run(
+        py,
+        async move { Ok(run_probes_once(&probes, &config).await) },
+    )
+}
+
+/// Awaitable variant of `set_probe` for applications that already run an
+/// asyncio event loop. Probes are awaited on the caller's loop; the returned
+/// awaitable completes once the server stops.
+#[pyfunction]
+#[pyo3(signature = (services = None, **options))]
+#[pyo3(
+    text_signature = "(services=None, *, host='0.0.0.0', port=3000, interval_seconds=5.0, \
+    stale_tree_intervals=3.0, root_name='medic', allow_sync=False, probe_timeout_seconds=10.0, \
+    fresh_timeout_seconds=10.0, drain_timeout_seconds=5.0, startup_grace_seconds=None, retries=0, \
+    retry_delay_seconds=0.5, cycle_deadline_seconds=None, max_concurrency=None, aggregator=None, \
+    aggregation='worst', non_critical_orange_degrades=False, empty_status=None, \
+    root_description=None, dashboard=True, dashboard_path='/', path_prefix='', \
+    allow_duplicates=False, on_start=None, on_stop=None, lenient_status=False, \
+    uptime_window_seconds=3600.0, colors=None, strict_status=False, max_stale_polls=3, \
+    eager_first_poll=True, startup_timeout_seconds=10.0, jitter_fraction=0.1, backoff_after=None, \
+    backoff_factor=2.0, max_backoff_seconds=300.0, circuit_breaker_after=None, \
+    circuit_breaker_cooldown_seconds=60.0, failure_threshold=1, success_threshold=1, \
+    flap_window=20, flap_threshold=None, flap_quiet_seconds=60.0, max_depth=32, max_nodes=10000, \
+    max_description_bytes=1024, log_level='info', log_json=False, log_filter=None)"
+)]
+pub fn serve_probe<'py>(
+    py: Python<'py>,
+    services: Option<&'py PyAny>,
+    options: Option<&PyDict>,
+) -> PyResult<&'py PyAny> {
+    let options = Options::new(py, "serve_probe", options)?;
+    let logging = Logging::from_options(&options)?;
+    let config = ProbeConfig::from_options(&options)?;
+    options.finish()?;
+    logging.init()?;
+
+    let (control, shutdown_rx) = register(services, &config, None)?;
+
+    pyo3_asyncio::tokio::future_into_py(py, async move {
+        let listener = bind(&config).await?;
+        serve(listener, control, config, shutdown_rx).await
     })
 }
+
+/// Creates an asyncio event loop running forever in a daemon thread, on
+/// which background servers await their probes.
+pub(crate) fn spawn_event_loop(py: Python<'_>) -> PyResult<&PyAny> {
+    let event_loop = py.import("asyncio")?.call_method0("new_event_loop")?;
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("target", event_loop.getattr("run_forever")?)?;
+    kwargs.set_item("name", "medic-event-loop")?;
+    kwargs.set_item("daemon", true)?;
+    py.import("threading")?
+        .getattr("Thread")?
+        .call((), Some(kwargs))?
+        .call_method0("start")?;
+    Ok(event_loop)
+}
+
+/// Starts the probe server in the background and returns immediately.
+///
+/// Probes are awaited on a dedicated asyncio event loop running in a daemon
+/// thread, so the caller's thread stays free.
+#[pyfunction]
+#[pyo3(signature = (services = None, **options))]
+#[pyo3(
+    text_signature = "(services=None, *, host='0.0.0.0', port=3000, interval_seconds=5.0, \
+    stale_tree_intervals=3.0, root_name='medic', allow_sync=False, probe_timeout_seconds=10.0, \
+    fresh_timeout_seconds=10.0, drain_timeout_seconds=5.0, startup_grace_seconds=None, retries=0, \
+    retry_delay_seconds=0.5, cycle_deadline_seconds=None, max_concurrency=None, aggregator=None, \
+    aggregation='worst', non_critical_orange_degrades=False, empty_status=None, \
+    root_description=None, dashboard=True, dashboard_path='/', path_prefix='', \
+    allow_duplicates=False, on_start=None, on_stop=None, lenient_status=False, \
+    uptime_window_seconds=3600.0, colors=None, strict_status=False, max_stale_polls=3, \
+    eager_first_poll=True, startup_timeout_seconds=10.0, jitter_fraction=0.1, backoff_after=None, \
+    backoff_factor=2.0, max_backoff_seconds=300.0, circuit_breaker_after=None, \
+    circuit_breaker_cooldown_seconds=60.0, failure_threshold=1, success_threshold=1, \
+    flap_window=20, flap_threshold=None, flap_quiet_seconds=60.0, max_depth=32, max_nodes=10000, \
+    max_description_bytes=1024, log_level='info', log_json=False, log_filter=None)"
+)]
+pub fn start_probe(
+    py: Python<'_>,
+    services: Option<&PyAny>,
+    options: Option<&PyDict>,
+) -> PyResult<ProbeHandle> {
+    let options = Options::new(py, "start_probe", options)?;
+    let logging = Logging::from_options(&options)?;
+    let config = ProbeConfig::from_options(&options)?;
+    options.finish()?;
+    logging.init()?;
+    let handle = start_in_background(
+        py,
+        ProbeRegistry::from_py(services, config.allow_duplicates)?,
+        config,
+        module_events(),
+    )?;
+    set_active(handle.control());
+    Ok(handle)
+}
+
+/// Binds the listener on the caller's thread, so startup errors are raised
+/// there, then serves from the shared runtime with probes awaited on a
+/// dedicated event loop thread, publishing transitions to `events`. The
+/// server is not made the target of the module-level functions.
+pub fn start_in_background(
+    py: Python<'_>,
+    probes: ProbeRegistry,
+    config: ProbeConfig,
+    events: Arc<Events>,
+) -> PyResult<ProbeHandle> {
+    let runtime = pyo3_asyncio::tokio::get_runtime();
+    let listener = py.allow_threads(|| runtime.block_on(bind(&config)))?;
+    let addr = listener.local_addr()?;
+    let url = base_url(addr, &config.path_prefix);
+
+    let event_loop = spawn_event_loop(py)?;
+    let task_locals = TaskLocals::new(event_loop).copy_context(py)?;
+    let (control, shutdown_rx) = new_control(probes, &config, events, Some(event_loop.into()));
+    let task = runtime.spawn(pyo3_asyncio::tokio::scope(
+        task_locals,
+        serve(listener, control.clone(), config, shutdown_rx),
+    ));
+
+    Ok(ProbeHandle::new(control, task, Some((addr, url))))
+}
+
+/// Starts polling in the background without any HTTP listener, for apps
+/// that serve the tree themselves through `render_health` or `get_status`.
+/// Returns the same handle as `start_probe`.
+#[pyfunction]
+#[pyo3(signature = (services = None, **options))]
+#[pyo3(
+    text_signature = "(services=None, *, interval_seconds=5.0, stale_tree_intervals=3.0, \
+    root_name='medic', allow_sync=False, probe_timeout_seconds=10.0, drain_timeout_seconds=5.0, \
+    startup_grace_seconds=None, retries=0, retry_delay_seconds=0.5, cycle_deadline_seconds=None, \
+    max_concurrency=None, aggregator=None, aggregation='worst', \
+    non_critical_orange_degrades=False, empty_status=None, root_description=None, \
+    allow_duplicates=False, lenient_status=False, uptime_window_seconds=3600.0, \
+    strict_status=False, max_stale_polls=3, startup_timeout_seconds=10.0, jitter_fraction=0.1, \
+    backoff_after=None, backoff_factor=2.0, max_backoff_seconds=300.0, circuit_breaker_after=None, \
+    circuit_breaker_cooldown_seconds=60.0, failure_threshold=1, success_threshold=1, \
+    flap_window=20, flap_threshold=None, flap_quiet_seconds=60.0, max_depth=32, max_nodes=10000, \
+    max_description_bytes=1024, log_level='info', log_json=False, log_filter=None)"
+)]
+pub fn start_polling(
+    py: Python<'_>,
+    services: Option<&PyAny>,
+    options: Option<&PyDict>,
+) -> PyResult<ProbeHandle> {
+    let options = Options::new(py, "start_polling", options)?.exclude(SERVER_OPTIONS)?;
+    let logging = Logging::from_options(&options)?;
+    let config = ProbeConfig {
+        eager_first_poll: false,
+        ..ProbeConfig::from_options(&options)?
+    };
+    options.finish()?;
+    logging.init()?;
+
+    let event_loop = spawn_event_loop(py)?;
+    let task_locals = TaskLocals::new(event_loop).copy_context(py)?;
+    let (control, shutdown_rx) = register(services, &config, Some(event_loop.into()))?;
+    let task = pyo3_asyncio::tokio::get_runtime().spawn(pyo3_asyncio::tokio::scope(
+        task_locals,
+        poll(control.clone(), config, shutdown_rx),
+    ));
+
+    Ok(ProbeHandle::new(control, task, None))
+}
@@ -0,0 +1,89 @@
+use crate::handle::ProbeControl;
+use pyo3::prelude::*;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Python-level SIGINT/SIGTERM handler that stops a server instead of
+/// raising `KeyboardInterrupt` inside the event loop.
+#[pyclass]
+struct StopOnSignal(Arc<ProbeControl>);
+
+#[pymethods]
+impl StopOnSignal {
+    fn __call__(&self, _signum: &PyAny, _frame: &PyAny) {
+        info!("Shutdown signal received");
+        self.0.request_stop();
+    }
+}
+
+/// Python handlers replaced by `install`, put back by `restore`.
+pub struct PreviousHandlers(Vec<(PyObject, PyObject)>);
+
+/// Makes SIGINT and SIGTERM stop `control` until `restore` is called.
+///
+/// Must run before `shutdown_signal` first registers with tokio, which then
+/// chains to the handler installed here. Python only allows this on the main
+/// thread; elsewhere nothing is installed.
+pub fn install(py: Python<'_>, control: &Arc<ProbeControl>) -> PyResult<PreviousHandlers> {
+    let threading = py.import("threading")?;
+    let current = threading.call_method0("current_thread")?;
+    if !current.is(threading.call_method0("main_thread")?) {
+        return Ok(PreviousHandlers(Vec::new()));
+    }
+
+    let signal = py.import("signal")?;
+    let handler = Py::new(py, StopOnSignal(control.clone()))?;
+    let mut previous = Vec::new();
+    for name in ["SIGINT", "SIGTERM"] {
+        let signum = signal.getattr(name)?;
+        let replaced = signal.call_method1("signal", (signum, handler.clone_ref(py)))?;
+        // `None` means the handler was not installed from Python.
+        let replaced = if replaced.is_none() {
+            signal.getattr("SIG_DFL")?
+        } else {
+            replaced
+        };
+        previous.push((signum.into(), replaced.into()));
+    }
+    Ok(PreviousHandlers(previous))
+}
+
+pub fn restore(py: Python<'_>, previous: PreviousHandlers) -> PyResult<()> {
+    let signal = py.import("signal")?;
+    for (signum, handler) in previous.0 {
+        signal.call_method1("signal", (signum, handler))?;
+    }
+    Ok(())
+}
+
+/// Completes on Ctrl-C, or SIGTERM on Unix. Never completes if the handlers
+/// cannot be registered.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("cannot listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!("cannot listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
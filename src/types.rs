@@ -1,84 +1,2103 @@
-use pyo3::exceptions::PyKeyError;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use pyo3::basic::CompareOp;
+use pyo3::exceptions::{
+    PyAttributeError, PyBaseException, PyKeyError, PyRuntimeError, PyTypeError, PyValueError,
+};
 use pyo3::prelude::*;
-use pyo3::types::PyDict;
-use serde::Serialize;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyList, PyLong, PyString, PyTuple, PyType};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::OnceLock;
 
-#[pyclass]
-#[derive(Serialize, Clone, Copy)]
+#[pyclass(module = "colonoscopy")]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum StatusColor {
     Red,
     Orange,
     Green,
+    /// Not checked yet, or the check's result could not be read.
+    Unknown,
 }
 
-#[pyclass]
-#[derive(Serialize, Clone)]
+impl StatusColor {
+    pub const ALL: [StatusColor; 4] = [
+        StatusColor::Red,
+        StatusColor::Unknown,
+        StatusColor::Orange,
+        StatusColor::Green,
+    ];
+
+    /// Orders colors from worst to best; Unknown sits between Red and
+    /// Orange. Backs `Ord`.
+    pub fn rank(self) -> u8 {
+        match self {
+            StatusColor::Red => 0,
+            StatusColor::Unknown => 1,
+            StatusColor::Orange => 2,
+            StatusColor::Green => 3,
+        }
+    }
+
+    /// The name used on the wire, e.g. `"GREEN"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            StatusColor::Red => "RED",
+            StatusColor::Orange => "ORANGE",
+            StatusColor::Green => "GREEN",
+            StatusColor::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// The color as an integer probes may report: 0=Red, 1=Orange,
+    /// 2=Green, 3=Unknown. Unlike `rank`, not an order.
+    pub fn level(self) -> i64 {
+        match self {
+            StatusColor::Red => 0,
+            StatusColor::Orange => 1,
+            StatusColor::Green => 2,
+            StatusColor::Unknown => 3,
+        }
+    }
+
+    /// Reads `level`'s scale back.
+    pub fn from_level(level: i64) -> Option<Self> {
+        StatusColor::ALL
+            .into_iter()
+            .find(|color| color.level() == level)
+    }
+
+    /// What this color counts as on a non-critical service when rolled up
+    /// into its parent: Red as Orange, and Orange as Green unless
+    /// `orange_degrades`.
+    pub fn as_non_critical(self, orange_degrades: bool) -> Self {
+        match self {
+            StatusColor::Red => StatusColor::Orange,
+            StatusColor::Orange if !orange_degrades => StatusColor::Green,
+            color => color,
+        }
+    }
+}
+
+impl PartialOrd for StatusColor {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StatusColor {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+/// Folds the colors of `iterable` with `pick`, raising ValueError when it
+/// is empty.
+fn extreme_color(
+    iterable: &PyAny,
+    method: &str,
+    pick: fn(StatusColor, StatusColor) -> StatusColor,
+) -> PyResult<StatusColor> {
+    let mut colors = iterable.iter()?.map(|c| color_from_py(c?));
+    let first = colors.next().ok_or_else(|| {
+        PyValueError::new_err(format!("StatusColor.{method}() of an empty iterable"))
+    })??;
+    colors.try_fold(first, |acc, c| Ok(pick(acc, c?)))
+}
+
+#[pymethods]
+impl StatusColor {
+    fn __repr__(&self) -> String {
+        format!("StatusColor.{self:?}")
+    }
+
+    fn __str__(&self) -> &'static str {
+        self.name()
+    }
+
+    fn __richcmp__(&self, other: &PyAny, op: CompareOp) -> PyObject {
+        let py = other.py();
+        match other.extract::<StatusColor>() {
+            Ok(other) => op.matches(self.cmp(&other)).into_py(py),
+            Err(_) => py.NotImplemented(),
+        }
+    }
+
+    fn __hash__(&self) -> u64 {
+        self.rank().into()
+    }
+
+    /// The color's level, which results may use in its place: 0=Red,
+    /// 1=Orange, 2=Green, 3=Unknown. Comparisons do not follow it.
+    fn __int__(&self) -> i64 {
+        self.level()
+    }
+
+    fn __copy__(&self) -> Self {
+        *self
+    }
+
+    fn __deepcopy__(&self, _memo: &PyAny) -> Self {
+        *self
+    }
+
+    /// Pickles by reference to the class attribute, e.g. `StatusColor.Red`.
+    fn __reduce__(&self, py: Python<'_>) -> PyResult<(PyObject, (PyObject, String))> {
+        let getattr = py.import("builtins")?.getattr("getattr")?;
+        let cls = py.get_type::<StatusColor>();
+        Ok((getattr.into(), (cls.into(), format!("{self:?}"))))
+    }
+
+    /// Parses a status name in any case, e.g. `"orange"`.
+    #[classmethod]
+    fn parse(_cls: &PyType, name: &str) -> PyResult<StatusColor> {
+        Ok(name.parse()?)
+    }
+
+    /// The worst of some colors, or of their names or levels.
+    #[classmethod]
+    fn worst(_cls: &PyType, iterable: &PyAny) -> PyResult<StatusColor> {
+        extreme_color(iterable, "worst", std::cmp::min)
+    }
+
+    /// The best of some colors, or of their names or levels.
+    #[classmethod]
+    fn best(_cls: &PyType, iterable: &PyAny) -> PyResult<StatusColor> {
+        extreme_color(iterable, "best", std::cmp::max)
+    }
+}
+
+/// A node of the health tree. `subservices` is copied on every read and
+/// write from Python: mutate the list, or a child in it, then assign the
+/// list back for the change to stick.
+#[pyclass(module = "colonoscopy")]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct ServiceStatus {
+    #[pyo3(get)]
     pub name: String,
+    #[pyo3(get)]
     pub status: StatusColor,
+    #[pyo3(get)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[pyo3(get)]
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub subservices: Vec<ServiceStatus>,
+    /// Free-form `key=value` tags, such as the owning team.
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub labels: HashMap<String, String>,
+    /// Set on services in maintenance, which the parent's color ignores.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub silenced: bool,
+    /// Set when the probe's latest result could not be read and this is
+    /// its last readable one instead.
+    #[pyo3(get)]
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub stale: bool,
+    /// When the status was last established, serialized in RFC 3339.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_checked: Option<DateTime<Utc>>,
+    /// How long the check took, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    /// Structured detail reported as-is, such as pool sizes or versions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Map<String, Value>>,
+    /// When the service took its current color, as far as the poller has
+    /// seen; reset when the color changes or the service reappears.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    /// Fraction of the polls within the uptime window that found the
+    /// service Green; for the root and groups, which are not polled
+    /// themselves, of the polls of the services below them.
+    #[pyo3(get)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<f64>,
+    /// How many polls `uptime` is computed over; low for new services.
+    #[pyo3(get)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime_samples: Option<usize>,
+    /// Red on a non-critical service only degrades its parent to Orange.
+    #[serde(skip_serializing_if = "is_critical", default = "critical_by_default")]
+    pub critical: bool,
 }
 
+fn is_critical(critical: &bool) -> bool {
+    *critical
+}
+
+fn critical_by_default() -> bool {
+    true
+}
+
+/// JSON Schema (draft 2020-12) of the `/health` body. Keep in step with
+/// `ServiceStatus`'s fields and their `skip_serializing_if`s.
+pub const HEALTH_SCHEMA: &str = r##"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "ServiceStatus",
+  "$ref": "#/$defs/ServiceStatus",
+  "$defs": {
+    "StatusColor": {
+      "type": "string",
+      "enum": ["RED", "ORANGE", "GREEN", "UNKNOWN"]
+    },
+    "ServiceStatus": {
+      "type": "object",
+      "required": ["name", "status"],
+      "properties": {
+        "name": {"type": "string"},
+        "status": {"$ref": "#/$defs/StatusColor"},
+        "description": {"type": "string"},
+        "subservices": {
+          "type": "array",
+          "items": {"$ref": "#/$defs/ServiceStatus"}
+        },
+        "labels": {
+          "type": "object",
+          "additionalProperties": {"type": "string"}
+        },
+        "silenced": {"type": "boolean"},
+        "stale": {"type": "boolean"},
+        "last_checked": {"type": "string", "format": "date-time"},
+        "since": {"type": "string", "format": "date-time"},
+        "uptime": {"type": "number", "minimum": 0, "maximum": 1},
+        "uptime_samples": {"type": "integer", "minimum": 1},
+        "latency_ms": {"type": "number", "minimum": 0},
+        "metadata": {"type": "object"},
+        "critical": {"type": "boolean"}
+      },
+      "additionalProperties": false
+    }
+  }
+}"##;
+
 #[pymethods]
 impl ServiceStatus {
+    /// An empty `name` takes the probe's name when returned by a probe;
+    /// blank names and trees beyond the default limits (32 levels, 10,000
+    /// services) raise ValueError. Descriptions are sanitized but kept
+    /// whole; a server cuts them to its `max_description_bytes` once it
+    /// reads the status.
     #[new]
-    #[pyo3(signature = (name, status, description=None, subservices=None))]
+    #[pyo3(signature = (
+        name,
+        status,
+        description=None,
+        subservices=None,
+        labels=None,
+        last_checked=None,
+        latency_ms=None,
+        metadata=None,
+        critical=true,
+    ))]
+    #[allow(clippy::too_many_arguments)]
     fn new(
         name: String,
         status: StatusColor,
         description: Option<String>,
         subservices: Option<Vec<ServiceStatus>>,
-    ) -> Self {
-        Self {
+        labels: Option<&PyAny>,
+        last_checked: Option<&PyAny>,
+        latency_ms: Option<f64>,
+        metadata: Option<&PyAny>,
+        critical: bool,
+    ) -> PyResult<Self> {
+        if !name.is_empty() {
+            check_name(&name)?;
+        }
+        let mut subservices = subservices.unwrap_or_default();
+        let limits = Limits::untruncated();
+        let mut reader = Reader::new(false, false, &limits);
+        check_subservices(&mut reader, &mut subservices)?;
+        Ok(Self {
+            name,
+            status,
+            description: description.map(|d| reader.sanitize(&d)),
+            subservices,
+            labels: labels
+                .map(|l| labels_from_py("labels", l))
+                .transpose()?
+                .unwrap_or_default(),
+            silenced: false,
+            stale: false,
+            last_checked: last_checked
+                .map(|t| timestamp_from_py("last_checked", t))
+                .transpose()?,
+            latency_ms,
+            metadata: metadata
+                .map(|m| metadata_from_py("metadata", m))
+                .transpose()?,
+            since: None,
+            uptime: None,
+            uptime_samples: None,
+            critical,
+        })
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!(
+            "ServiceStatus(name={}, status={}, subservices={})",
+            self.name.to_object(py).as_ref(py).repr()?,
+            self.status.name(),
+            self.subservices.len()
+        ))
+    }
+
+    /// The tree, one `name: STATUS (description)` line per service.
+    fn __str__(&self) -> String {
+        let mut out = String::new();
+        self.write_tree(&mut out, 0);
+        out
+    }
+
+    /// The `/health` body for this tree, as nested dicts and lists.
+    fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let value = serde_json::to_value(self).map_err(serialize_error)?;
+        json_to_py(py, &value)
+    }
+
+    /// The `/health` body for this tree, indented by `indent` spaces per
+    /// level if given.
+    #[pyo3(signature = (indent=None))]
+    fn to_json(&self, indent: Option<usize>) -> PyResult<String> {
+        let Some(indent) = indent else {
+            return serde_json::to_string(self).map_err(serialize_error);
+        };
+        let indent = b" ".repeat(indent);
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(&indent);
+        let mut out = Vec::new();
+        self.serialize(&mut serde_json::Serializer::with_formatter(
+            &mut out, formatter,
+        ))
+        .map_err(serialize_error)?;
+        Ok(String::from_utf8(out).expect("serde_json writes UTF-8"))
+    }
+
+    /// The subservice at `path`, e.g. `"external-api.auth"`, or `None`.
+    /// The path starts below this node, so it never includes this node's
+    /// own name; an empty path returns this node. Of same-named siblings,
+    /// the first is taken.
+    #[pyo3(name = "find", signature = (path, sep = "."))]
+    fn py_find(&self, path: &str, sep: &str) -> PyResult<Option<ServiceStatus>> {
+        if sep.is_empty() {
+            return Err(PyValueError::new_err("sep must not be empty"));
+        }
+        let segments: Vec<&str> = match path {
+            "" => Vec::new(),
+            path => path.split(sep).collect(),
+        };
+        Ok(self.find(&segments).cloned())
+    }
+
+    /// `(path, status, description)` for every node, depth first. Paths
+    /// join names with `sep` below this node, whose own row has path `""`.
+    #[pyo3(name = "flatten", signature = (sep = "."))]
+    fn py_flatten(&self, sep: &str) -> Vec<(String, StatusColor, Option<String>)> {
+        flatten_with(self, sep)
+    }
+
+    /// A new tree combining this one with `other`, which keeps this root's
+    /// name and leaves both inputs untouched:
+    /// - the worst of the two colors wins;
+    /// - differing descriptions are joined as `"mine; theirs"`, a missing
+    ///   one yields to the other;
+    /// - labels and metadata are united, this side winning on conflicts;
+    ///   the later `last_checked` is kept and this side's latency if any;
+    /// - the node is critical or stale if either side is and silenced if
+    ///   both are;
+    /// - each of `other`'s children merges into the first same-named child
+    ///   here, or is appended after them, in `other`'s order.
+    #[pyo3(name = "merge")]
+    fn py_merge(&self, other: PyRef<'_, ServiceStatus>) -> ServiceStatus {
+        self.merge(&other)
+    }
+
+    /// Whether this node and every descendant are Green.
+    fn is_healthy(&self) -> bool {
+        self.walk(".")
+            .iter()
+            .all(|(_, node)| node.status == StatusColor::Green)
+    }
+
+    /// `(path, node)` of the worst-colored node of this subtree, this one
+    /// included with path `""`. Ties go to the first in depth-first order.
+    fn worst_descendant(&self) -> (String, ServiceStatus) {
+        let (path, node) = self
+            .walk(".")
+            .into_iter()
+            .min_by_key(|(_, node)| node.status)
+            .expect("walk yields at least this node");
+        (path, node.clone())
+    }
+
+    /// How many nodes of this subtree have each color, keyed by name.
+    fn count_by_status(&self) -> HashMap<&'static str, usize> {
+        let mut counts: HashMap<&'static str, usize> = StatusColor::ALL
+            .into_iter()
+            .map(|color| (color.name(), 0))
+            .collect();
+        for (_, node) in self.walk(".") {
+            *counts.entry(node.status.name()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// The JSON Schema of `to_dict()`'s output, i.e. the `/health` body.
+    #[classmethod]
+    fn json_schema(_cls: &PyType, py: Python<'_>) -> PyResult<PyObject> {
+        let schema: Value =
+            serde_json::from_str(HEALTH_SCHEMA).expect("HEALTH_SCHEMA is valid JSON");
+        json_to_py(py, &schema)
+    }
+
+    /// Checked like the `name` given to the constructor.
+    #[setter(name)]
+    fn set_name(&mut self, name: String) -> PyResult<()> {
+        if !name.is_empty() {
+            check_name(&name)?;
+        }
+        self.name = name;
+        Ok(())
+    }
+
+    /// Accepts anything `color_from_py` does.
+    #[setter(status)]
+    fn set_status(&mut self, status: &PyAny) -> PyResult<()> {
+        self.status = color_from_py(status)?;
+        Ok(())
+    }
+
+    /// Sanitized, but not cut, like the `description` given to the
+    /// constructor.
+    #[setter(description)]
+    fn set_description(&mut self, description: Option<String>) {
+        let limits = Limits::untruncated();
+        let reader = Reader::new(false, false, &limits);
+        self.description = description.map(|d| reader.sanitize(&d));
+    }
+
+    /// Checked like the `subservices` given to the constructor; on error
+    /// the old ones are kept.
+    #[setter(subservices)]
+    fn set_subservices(&mut self, mut subservices: Vec<ServiceStatus>) -> PyResult<()> {
+        let limits = Limits::untruncated();
+        check_subservices(&mut Reader::new(false, false, &limits), &mut subservices)?;
+        self.subservices = subservices;
+        Ok(())
+    }
+
+    /// The color the parent's is based on: the worst of this subtree. See
+    /// `effective_status` in Rust.
+    #[getter(effective_status)]
+    fn py_effective_status(&self) -> StatusColor {
+        self.effective_status()
+    }
+
+    /// When the service took its current color, as an aware `datetime`.
+    #[getter]
+    fn since(&self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        self.since.map(|t| datetime_to_py(py, t)).transpose()
+    }
+
+    /// A leaf with no subservices yet, e.g.
+    /// `ServiceStatus.green("api").add_sub(ServiceStatus.red("auth"))`.
+    #[classmethod]
+    #[pyo3(signature = (name, description = None))]
+    fn green(_cls: &PyType, name: String, description: Option<String>) -> PyResult<Self> {
+        Self::leaf(name, StatusColor::Green, description)
+    }
+
+    #[classmethod]
+    #[pyo3(signature = (name, description = None))]
+    fn orange(_cls: &PyType, name: String, description: Option<String>) -> PyResult<Self> {
+        Self::leaf(name, StatusColor::Orange, description)
+    }
+
+    #[classmethod]
+    #[pyo3(signature = (name, description = None))]
+    fn red(_cls: &PyType, name: String, description: Option<String>) -> PyResult<Self> {
+        Self::leaf(name, StatusColor::Red, description)
+    }
+
+    #[classmethod]
+    #[pyo3(signature = (name, description = None))]
+    fn unknown(_cls: &PyType, name: String, description: Option<String>) -> PyResult<Self> {
+        Self::leaf(name, StatusColor::Unknown, description)
+    }
+
+    /// A leaf describing a caught exception as `Type: message`, Red unless
+    /// `status` says otherwise. `exc` is the exception or a
+    /// `sys.exc_info()` triple; with `traceback`, its last three frames
+    /// follow. The description is sanitized like any other, and cut only
+    /// once a server reads it.
+    #[classmethod]
+    #[pyo3(signature = (name, exc, status = None, traceback = false))]
+    fn from_exception(
+        _cls: &PyType,
+        name: String,
+        exc: &PyAny,
+        status: Option<&PyAny>,
+        traceback: bool,
+    ) -> PyResult<Self> {
+        let py = exc.py();
+        let (value, tb) = match exc.downcast::<PyTuple>() {
+            Ok(triple) if triple.len() == 3 => (triple.get_item(1)?, triple.get_item(2)?),
+            _ => (
+                exc,
+                exc.getattr("__traceback__")
+                    .unwrap_or_else(|_| py.None().into_ref(py)),
+            ),
+        };
+        if !value.is_instance_of::<PyBaseException>() {
+            return Err(PyTypeError::new_err(format!(
+                "exc must be an exception or a sys.exc_info() triple, got {}",
+                exc.get_type().name()?
+            )));
+        }
+        let type_name = value.get_type().name()?;
+        let message = value.str()?;
+        let mut description = match message.to_str()? {
+            "" => type_name.to_owned(),
+            message => format!("{type_name}: {message}"),
+        };
+        if traceback && !tb.is_none() {
+            let frames: Vec<String> = py
+                .import("traceback")?
+                .call_method1("format_tb", (tb, -3))?
+                .extract()?;
+            description.push('\n');
+            description.push_str(frames.concat().trim_end());
+        }
+        let status = status.map(color_from_py).transpose()?;
+        Self::leaf(name, status.unwrap_or(StatusColor::Red), Some(description))
+    }
+
+    /// Appends a copy of `child` to the subservices and returns this
+    /// status, for chaining. Later changes to `child` are not reflected.
+    fn add_sub(mut slf: PyRefMut<'_, Self>, child: ServiceStatus) -> PyResult<PyRefMut<'_, Self>> {
+        slf.subservices.push(child);
+        let limits = Limits::untruncated();
+        if let Err(e) = check_subservices(
+            &mut Reader::new(false, false, &limits),
+            &mut slf.subservices,
+        ) {
+            slf.subservices.pop();
+            return Err(e);
+        }
+        Ok(slf)
+    }
+
+    /// Builds a status from a dict shaped like a probe result. With
+    /// `strict`, unknown keys are rejected and errors name the path to the
+    /// offending element, e.g. `subservices[2].status: ...`.
+    #[classmethod]
+    #[pyo3(signature = (dict, strict=false))]
+    fn from_dict(_cls: &PyType, dict: &PyDict, strict: bool) -> PyResult<Self> {
+        status_from_py(dict, None, false, strict, &Limits::untruncated())
+    }
+
+    /// Structural: derived from the names, colors and descriptions of the
+    /// whole tree, so it changes whenever any descendant does. Do not
+    /// mutate a status while it sits in a set or a dict key.
+    fn __hash__(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for (path, node) in self.walk(".") {
+            path.hash(&mut hasher);
+            node.name.hash(&mut hasher);
+            node.status.hash(&mut hasher);
+            node.description.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Copies the whole tree: subservices are values, never shared.
+    fn __copy__(&self) -> Self {
+        self.clone()
+    }
+
+    fn __deepcopy__(&self, _memo: &PyAny) -> Self {
+        self.clone()
+    }
+
+    /// Pickles as the full tree in its JSON form, restored by
+    /// `__setstate__`.
+    fn __reduce__(&self, py: Python<'_>) -> PyResult<(PyObject, (String, StatusColor), String)> {
+        let state = serde_json::to_string(self).map_err(serialize_error)?;
+        let cls = py.get_type::<ServiceStatus>();
+        Ok((cls.into(), (self.name.clone(), self.status), state))
+    }
+
+    fn __setstate__(&mut self, state: &str) -> PyResult<()> {
+        *self = serde_json::from_str(state)
+            .map_err(|e| PyValueError::new_err(format!("invalid ServiceStatus state: {e}")))?;
+        Ok(())
+    }
+
+    /// Compares whole trees, subservices included.
+    fn __richcmp__(&self, other: &PyAny, op: CompareOp) -> PyObject {
+        let py = other.py();
+        let Ok(other) = other.downcast::<PyCell<ServiceStatus>>() else {
+            return py.NotImplemented();
+        };
+        let Ok(other) = other.try_borrow() else {
+            return py.NotImplemented();
+        };
+        match op {
+            CompareOp::Eq => (*self == *other).into_py(py),
+            CompareOp::Ne => (*self != *other).into_py(py),
+            _ => py.NotImplemented(),
+        }
+    }
+}
+
+impl ServiceStatus {
+    fn leaf(name: String, status: StatusColor, description: Option<String>) -> PyResult<Self> {
+        Self::new(
             name,
             status,
             description,
-            subservices: subservices.unwrap_or_default(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            true,
+        )
+    }
+
+    /// Sets `since` across this tree: kept from the same-named node of
+    /// `previous` while the color is unchanged, `now` otherwise.
+    pub fn carry_since(&mut self, previous: Option<&ServiceStatus>, now: DateTime<Utc>) {
+        self.since = match previous {
+            Some(previous) if previous.status == self.status => previous.since.or(Some(now)),
+            _ => Some(now),
+        };
+        for sub in &mut self.subservices {
+            let previous = previous.and_then(|p| p.subservices.iter().find(|s| s.name == sub.name));
+            sub.carry_since(previous, now);
         }
     }
+
+    /// Writes this status and its subservices one per line, indented by
+    /// depth.
+    fn write_tree(&self, out: &mut String, depth: usize) {
+        if depth > 0 {
+            out.push('\n');
+        }
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(&format!("{}: {}", self.name, self.status.name()));
+        if let Some(description) = &self.description {
+            out.push_str(&format!(" ({description})"));
+        }
+        for sub in &self.subservices {
+            sub.write_tree(out, depth + 1);
+        }
+    }
+
+    /// The worst color of this subtree, which is what the parent's color is
+    /// based on, as opposed to `status`, this node's own. Silenced
+    /// descendants do not count and Red on a non-critical one only counts
+    /// as Orange.
+    pub fn effective_status(&self) -> StatusColor {
+        self.rolled_up_status(true)
+    }
+
+    /// Like `effective_status`, except that Orange on a non-critical
+    /// descendant only counts when `non_critical_orange_degrades`.
+    pub fn rolled_up_status(&self, non_critical_orange_degrades: bool) -> StatusColor {
+        let mut worst = self.status;
+        let mut stack: Vec<&ServiceStatus> = self.subservices.iter().collect();
+        while let Some(node) = stack.pop() {
+            if node.silenced {
+                continue;
+            }
+            worst = worst.min(match node.critical {
+                true => node.status,
+                false => node.status.as_non_critical(non_critical_orange_degrades),
+            });
+            stack.extend(&node.subservices);
+        }
+        worst
+    }
+
+    /// Every node with its path from this one joined by `sep`, empty for
+    /// this node itself, parents first. Walks iteratively, so arbitrarily
+    /// deep trees are fine.
+    pub fn walk(&self, sep: &str) -> Vec<(String, &ServiceStatus)> {
+        let mut out = Vec::new();
+        let mut stack = vec![(String::new(), self)];
+        while let Some((path, node)) = stack.pop() {
+            for sub in node.subservices.iter().rev() {
+                let sub_path = if std::ptr::eq(node, self) {
+                    sub.name.clone()
+                } else {
+                    format!("{path}{sep}{}", sub.name)
+                };
+                stack.push((sub_path, sub));
+            }
+            out.push((path, node));
+        }
+        out
+    }
+
+    /// `(path, status, description)` for every node, depth first, with
+    /// dotted paths as in `walk`.
+    pub fn flatten(&self) -> Vec<(String, StatusColor, Option<String>)> {
+        flatten_with(self, ".")
+    }
+
+    /// A new tree combining this one with `other`, as described on the
+    /// Python `merge`.
+    pub fn merge(&self, other: &ServiceStatus) -> ServiceStatus {
+        let mut merged = self.clone();
+        merged.merge_from(other);
+        merged
+    }
+
+    fn merge_from(&mut self, other: &ServiceStatus) {
+        self.status = self.status.min(other.status);
+        self.description = match (self.description.take(), &other.description) {
+            (Some(mine), Some(theirs)) if mine != *theirs => Some(format!("{mine}; {theirs}")),
+            (mine, theirs) => mine.or_else(|| theirs.clone()),
+        };
+        for (key, value) in &other.labels {
+            self.labels
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        if let Some(theirs) = &other.metadata {
+            let mine = self.metadata.get_or_insert_with(Map::new);
+            for (key, value) in theirs {
+                mine.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        self.last_checked = self.last_checked.max(other.last_checked);
+        self.latency_ms = self.latency_ms.or(other.latency_ms);
+        self.critical |= other.critical;
+        self.silenced &= other.silenced;
+        self.stale |= other.stale;
+        for theirs in &other.subservices {
+            match self.subservices.iter_mut().find(|s| s.name == theirs.name) {
+                Some(mine) => mine.merge_from(theirs),
+                None => self.subservices.push(theirs.clone()),
+            }
+        }
+    }
+
+    /// The descendant reached by following subservice names, e.g.
+    /// `["external-api", "auth"]`, taking the first of same-named siblings.
+    /// No segments means this node itself.
+    pub fn find(&self, segments: &[&str]) -> Option<&ServiceStatus> {
+        segments.iter().try_fold(self, |node, name| {
+            node.subservices.iter().find(|s| s.name == *name)
+        })
+    }
+}
+
+/// Accepts an RFC 3339 string, seconds since the Unix epoch, or a
+/// `datetime`, naive ones being read as local time.
+pub fn timestamp_from_py(arg: &str, obj: &PyAny) -> PyResult<DateTime<Utc>> {
+    if let Ok(text) = obj.downcast::<PyString>() {
+        let text = text.to_str()?;
+        return DateTime::parse_from_rfc3339(text)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|e| {
+                PyValueError::new_err(format!("{arg} {text:?} is not an RFC 3339 timestamp: {e}"))
+            });
+    }
+    let wrong_type = || {
+        PyTypeError::new_err(format!(
+            "{arg} must be a datetime, str or number, got {}",
+            obj.get_type().name().unwrap_or("?")
+        ))
+    };
+    let seconds: f64 = if obj.hasattr("timestamp")? {
+        obj.call_method0("timestamp")?.extract()?
+    } else if obj.is_instance_of::<PyBool>() {
+        return Err(wrong_type());
+    } else {
+        obj.extract().map_err(|_| wrong_type())?
+    };
+    Some(seconds)
+        .filter(|s| s.is_finite())
+        .and_then(|s| {
+            // Nanoseconds count forward from the floor, so that -1.25 is 0.75s
+            // past -2 rather than 0.25s before -1.
+            let nanos = ((s - s.floor()) * 1e9) as u32;
+            DateTime::from_timestamp(s.floor() as i64, nanos.min(999_999_999))
+        })
+        .ok_or_else(|| PyValueError::new_err(format!("{arg} is out of range, got {obj}")))
+}
+
+/// Converts a JSON-serializable dict, so that a bad value fails when the
+/// status is built rather than when `/health` is served.
+pub fn metadata_from_py(arg: &str, obj: &PyAny) -> PyResult<Map<String, Value>> {
+    if !obj.is_instance_of::<PyDict>() {
+        return Err(PyTypeError::new_err(format!(
+            "{arg} must be a dict, got {}",
+            obj.get_type().name().unwrap_or("?")
+        )));
+    }
+    match json_from_py(arg, obj, 0)? {
+        Value::Object(map) => Ok(map),
+        _ => unreachable!("dicts convert to objects"),
+    }
+}
+
+/// Converts `obj`, found at `path`, into a JSON value.
+fn json_from_py(path: &str, obj: &PyAny, depth: usize) -> PyResult<Value> {
+    if depth >= MAX_DEPTH {
+        return Err(PyValueError::new_err(format!(
+            "{path} nested deeper than {MAX_DEPTH} levels"
+        )));
+    }
+    let not_serializable = || {
+        PyTypeError::new_err(format!(
+            "{path} is not JSON serializable, got {}",
+            obj.get_type().name().unwrap_or("?")
+        ))
+    };
+    if obj.is_none() {
+        Ok(Value::Null)
+    } else if let Ok(flag) = obj.downcast::<PyBool>() {
+        Ok(Value::Bool(flag.is_true()))
+    } else if obj.is_instance_of::<PyLong>() {
+        if let Ok(n) = obj.extract::<i64>() {
+            Ok(n.into())
+        } else {
+            obj.extract::<u64>()
+                .map(Value::from)
+                .map_err(|_| not_serializable())
+        }
+    } else if obj.is_instance_of::<PyFloat>() {
+        serde_json::Number::from_f64(obj.extract()?)
+            .map(Value::Number)
+            .ok_or_else(not_serializable)
+    } else if let Ok(text) = obj.downcast::<PyString>() {
+        Ok(Value::String(text.to_str()?.to_owned()))
+    } else if obj.is_instance_of::<PyList>() || obj.is_instance_of::<PyTuple>() {
+        obj.iter()?
+            .enumerate()
+            .map(|(i, item)| json_from_py(&format!("{path}[{i}]"), item?, depth + 1))
+            .collect()
+    } else if let Ok(dict) = obj.downcast::<PyDict>() {
+        dict.iter()
+            .map(|(key, value)| {
+                let key: String = key.extract().map_err(|_| {
+                    PyTypeError::new_err(format!("{path} keys must be strings, got {key:?}"))
+                })?;
+                let value = json_from_py(&format!("{path}[{key:?}]"), value, depth + 1)?;
+                Ok((key, value))
+            })
+            .collect()
+    } else {
+        Err(not_serializable())
+    }
+}
+
+fn flatten_with(node: &ServiceStatus, sep: &str) -> Vec<(String, StatusColor, Option<String>)> {
+    node.walk(sep)
+        .into_iter()
+        .map(|(path, node)| (path, node.status, node.description.clone()))
+        .collect()
+}
+
+fn serialize_error(e: serde_json::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("failed to serialize status: {e}"))
+}
+
+/// Converts a JSON value into the matching Python builtins.
+fn json_to_py(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(flag) => flag.into_py(py),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => i.into_py(py),
+            (_, Some(u)) => u.into_py(py),
+            _ => n.as_f64().into_py(py),
+        },
+        Value::String(text) => text.into_py(py),
+        Value::Array(items) => {
+            let items = items
+                .iter()
+                .map(|item| json_to_py(py, item))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items).into()
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, item) in map {
+                dict.set_item(key, json_to_py(py, item)?)?;
+            }
+            dict.into()
+        }
+    })
+}
+
+/// An aware `datetime.datetime` in UTC.
+fn datetime_to_py(py: Python<'_>, t: DateTime<Utc>) -> PyResult<PyObject> {
+    let datetime = py.import("datetime")?;
+    let utc = datetime.getattr("timezone")?.getattr("utc")?;
+    let args = (
+        t.year(),
+        t.month(),
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second(),
+        t.nanosecond() / 1000 % 1_000_000,
+        utc,
+    );
+    Ok(datetime.getattr("datetime")?.call1(args)?.into())
+}
+
+/// Status names accepted from Python.
+const STATUS_NAMES: &str = "GREEN, ORANGE, RED, UNKNOWN";
+
+/// A string that names no `StatusColor`; raised as ValueError.
+#[derive(Debug)]
+pub struct UnknownStatus(String);
+
+impl fmt::Display for UnknownStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown status {:?}; expected one of {STATUS_NAMES}",
+            self.0
+        )
+    }
 }
 
-pub fn py_status_to_rust(color: &str) -> StatusColor {
-    match color {
-        "GREEN" => StatusColor::Green,
-        "ORANGE" => StatusColor::Orange,
-        _ => StatusColor::Red,
+impl std::error::Error for UnknownStatus {}
+
+impl From<UnknownStatus> for PyErr {
+    fn from(e: UnknownStatus) -> Self {
+        PyValueError::new_err(e.to_string())
     }
 }
 
-pub fn dict_to_status(dict: &PyDict) -> PyResult<ServiceStatus> {
-    let name: String = dict
-        .get_item("name")?
-        .ok_or_else(|| PyKeyError::new_err("name"))?
-        .extract()?;
-    let status_str: String = dict
-        .get_item("status")?
-        .ok_or_else(|| PyKeyError::new_err("status"))?
-        .extract()?;
-    let description: Option<String> = dict
-        .get_item("description")?
-        .map(|d| d.extract())
+impl FromStr for StatusColor {
+    type Err = UnknownStatus;
+
+    /// Matches a status name, ignoring case and surrounding whitespace.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        StatusColor::ALL
+            .into_iter()
+            .find(|color| color.name().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| UnknownStatus(name.to_owned()))
+    }
+}
+
+/// Accepts a `StatusColor`, its name in any case, or its level (0=Red,
+/// 1=Orange, 2=Green, 3=Unknown).
+pub fn color_from_py(obj: &PyAny) -> PyResult<StatusColor> {
+    status_value_from_py(obj, false)
+}
+
+/// `color_from_py` for a probe result. With `lenient`, unknown names and
+/// levels read as Red instead of raising.
+fn status_value_from_py(obj: &PyAny, lenient: bool) -> PyResult<StatusColor> {
+    if let Ok(color) = obj.extract::<StatusColor>() {
+        return Ok(color);
+    }
+    if let Ok(name) = obj.downcast::<PyString>() {
+        return py_status_to_rust(name.to_str()?, lenient);
+    }
+    if obj.is_instance_of::<PyLong>() && !obj.is_instance_of::<PyBool>() {
+        let level: i64 = obj.extract()?;
+        return match StatusColor::from_level(level) {
+            Some(color) => Ok(color),
+            None if lenient => Ok(StatusColor::Red),
+            None => Err(PyValueError::new_err(format!(
+                "unknown status {level}; expected 0 (RED), 1 (ORANGE), 2 (GREEN) or 3 (UNKNOWN)"
+            ))),
+        };
+    }
+    Err(PyTypeError::new_err(format!(
+        "status must be a StatusColor, str or int, got {}",
+        obj.get_type().name()?
+    )))
+}
+
+/// The color of a probe result's `status`, Unknown when it is missing,
+/// `None` or blank so that the service still shows up.
+fn result_status(status: Option<&PyAny>, lenient: bool) -> PyResult<StatusColor> {
+    let Some(status) = status.filter(|s| !s.is_none()) else {
+        return Ok(StatusColor::Unknown);
+    };
+    if let Ok(name) = status.downcast::<PyString>() {
+        if name.to_str()?.trim().is_empty() {
+            return Ok(StatusColor::Unknown);
+        }
+    }
+    status_value_from_py(status, lenient)
+}
+
+/// Extracts a `dict[str, str]`, naming `arg` and the offending key when a
+/// value is not a string.
+pub fn labels_from_py(arg: &str, obj: &PyAny) -> PyResult<HashMap<String, String>> {
+    let dict: &PyDict = obj.downcast().map_err(|_| {
+        PyTypeError::new_err(format!(
+            "{arg} must be a dict of strings, got {}",
+            obj.get_type().name().unwrap_or("?")
+        ))
+    })?;
+    dict.iter()
+        .map(|(key, value)| {
+            let key: String = key.extract().map_err(|_| {
+                PyTypeError::new_err(format!("{arg} keys must be strings, got {key:?}"))
+            })?;
+            let value: String = value.extract().map_err(|_| {
+                PyTypeError::new_err(format!(
+                    "{arg}[{key:?}] must be a string, got {}",
+                    value.get_type().name().unwrap_or("?")
+                ))
+            })?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// Parses a status name from a probe result, in any case. With `lenient`,
+/// unknown names read as Red instead of raising.
+pub fn py_status_to_rust(color: &str, lenient: bool) -> PyResult<StatusColor> {
+    match color.parse() {
+        Ok(status) => Ok(status),
+        Err(_) if lenient => Ok(StatusColor::Red),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// How deep `subservices` and `metadata` may nest by default, guarding
+/// against self-referential structures.
+const MAX_DEPTH: usize = 32;
+
+/// Bounds on the shape of a health tree, so that a runaway probe result
+/// cannot exhaust memory or the stack while it is read or served.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Levels of `subservices` below the result itself.
+    pub max_depth: usize,
+    /// Nodes in one result, the result itself included.
+    pub max_nodes: usize,
+    /// Bytes of a description; longer ones are truncated.
+    pub max_description_bytes: usize,
+}
+
+impl Limits {
+    /// The default limits, except that descriptions are left whole: a
+    /// status built directly from Python is only truncated once a server
+    /// reads it, to that server's `max_description_bytes`.
+    fn untruncated() -> Self {
+        Self {
+            max_description_bytes: usize::MAX,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: MAX_DEPTH,
+            max_nodes: 10_000,
+            max_description_bytes: 1024,
+        }
+    }
+}
+
+const TRUNCATED: &str = "… [truncated]";
+
+/// The smallest `max_description_bytes` that leaves room for the
+/// `"… [truncated]"` marker.
+pub const MIN_DESCRIPTION_BYTES: usize = TRUNCATED.len();
+
+/// Strips ANSI escape sequences and control characters other than newlines
+/// and tabs, then cuts `text` on a character boundary so that it fits in
+/// `max_bytes` along with a `"… [truncated]"` marker, or without it when
+/// `max_bytes` is below `MIN_DESCRIPTION_BYTES`.
+pub fn sanitize_description(text: &str, max_bytes: usize) -> String {
+    let mut clean = String::with_capacity(text.len().min(max_bytes));
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{1b}' => match chars.next() {
+                // CSI, e.g. colors: parameters up to a final byte in @..~.
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, e.g. hyperlinks: up to BEL or ESC \.
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\u{7}' || (c == '\u{1b}' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\n' | '\t' => clean.push(c),
+            c if c.is_control() => {}
+            c => clean.push(c),
+        }
+    }
+    if clean.len() <= max_bytes {
+        return clean;
+    }
+    let marker = if max_bytes < MIN_DESCRIPTION_BYTES {
+        ""
+    } else {
+        TRUNCATED
+    };
+    let mut cut = max_bytes - marker.len();
+    while !clean.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    clean.truncate(cut);
+    clean.push_str(marker);
+    clean
+}
+
+/// State kept while reading one probe result.
+struct Reader<'a> {
+    lenient: bool,
+    /// Rejects unknown dict keys and names the path to the failing element.
+    strict: bool,
+    limits: &'a Limits,
+    nodes: usize,
+    /// Segments such as `subservices[2]` leading to the node being read.
+    path: Vec<String>,
+    /// Whether the error on its way up already carries its path.
+    located: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(lenient: bool, strict: bool, limits: &'a Limits) -> Self {
+        Self {
+            lenient,
+            strict,
+            limits,
+            nodes: 0,
+            path: Vec::new(),
+            located: false,
+        }
+    }
+
+    /// Names `key` and the path to it in errors from `result`, when strict.
+    fn field<T>(&mut self, py: Python<'_>, key: &str, result: PyResult<T>) -> PyResult<T> {
+        match result {
+            Err(e) if self.strict => Err(self.locate(py, Some(key), e)),
+            result => result,
+        }
+    }
+
+    /// Prefixes `err` with the current path, followed by `field` if given,
+    /// unless a deeper call already did.
+    fn locate(&mut self, py: Python<'_>, field: Option<&str>, err: PyErr) -> PyErr {
+        if self.located {
+            return err;
+        }
+        self.located = true;
+        let path: Vec<&str> = self.path.iter().map(String::as_str).chain(field).collect();
+        if path.is_empty() {
+            return err;
+        }
+        PyErr::from_type(
+            err.get_type(py),
+            format!("{}: {}", path.join("."), err.value(py)),
+        )
+    }
+
+    /// Accounts for one more node at `depth`.
+    fn enter(&mut self, depth: usize) -> PyResult<()> {
+        if depth > self.limits.max_depth {
+            return Err(PyValueError::new_err(format!(
+                "subservices nested deeper than {} levels",
+                self.limits.max_depth
+            )));
+        }
+        self.nodes += 1;
+        if self.nodes > self.limits.max_nodes {
+            return Err(PyValueError::new_err(format!(
+                "more than {} services in one result",
+                self.limits.max_nodes
+            )));
+        }
+        Ok(())
+    }
+
+    /// Accounts for an already built subtree rooted at `depth`, whose
+    /// names must all be set, and sanitizes its descriptions.
+    fn enter_tree(&mut self, root: &mut ServiceStatus, depth: usize) -> PyResult<()> {
+        let mut stack = vec![(root, depth)];
+        while let Some((node, depth)) = stack.pop() {
+            check_name(&node.name)?;
+            self.enter(depth)?;
+            node.description = node.description.take().map(|d| self.sanitize(&d));
+            stack.extend(node.subservices.iter_mut().map(|sub| (sub, depth + 1)));
+        }
+        Ok(())
+    }
+
+    fn sanitize(&self, description: &str) -> String {
+        sanitize_description(description, self.limits.max_description_bytes)
+    }
+}
+
+/// Checks the subservices of a root with `reader`'s limits, sanitizing
+/// their descriptions.
+fn check_subservices(reader: &mut Reader<'_>, subservices: &mut [ServiceStatus]) -> PyResult<()> {
+    reader.enter(0)?;
+    for sub in subservices {
+        reader.enter_tree(sub, 1)?;
+    }
+    Ok(())
+}
+
+/// Rejects empty and whitespace-only service names.
+fn check_name(name: &str) -> PyResult<()> {
+    if name.trim().is_empty() {
+        return Err(PyValueError::new_err(format!(
+            "service names must not be blank, got {name:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Converts each item of the children found under `key` at nesting
+/// `depth`. Errors keep their type and are prefixed with the offending
+/// index, or with the whole path to it in strict mode.
+fn subservices_from_py(
+    key: &str,
+    obj: &PyAny,
+    depth: usize,
+    reader: &mut Reader<'_>,
+) -> PyResult<Vec<ServiceStatus>> {
+    let py = obj.py();
+    let mut subservices = Vec::new();
+    for (i, sub) in obj.iter()?.enumerate() {
+        reader.path.push(format!("{key}[{i}]"));
+        let converted = match sub.and_then(|sub| convert(sub, None, depth + 1, reader)) {
+            Err(e) if reader.strict => Err(reader.locate(py, None, e)),
+            Err(e) => Err(PyErr::from_type(
+                e.get_type(py),
+                format!("{key}[{i}]: {}", e.value(py)),
+            )),
+            Ok(sub) => Ok(sub),
+        };
+        reader.path.pop();
+        subservices.push(converted?);
+    }
+    Ok(subservices)
+}
+
+/// Keys a dict may nest its children under, in order of preference.
+const SUBSERVICE_KEYS: [&str; 3] = ["subservices", "children", "checks"];
+
+/// Keys a dict may set its labels under, in order of preference.
+const LABEL_KEYS: [&str; 2] = ["labels", "tags"];
+
+/// The first of `keys` set in `dict` with its value. Raises ValueError when
+/// another one is set to something different.
+fn first_of<'py>(
+    dict: &'py PyDict,
+    keys: &[&'static str],
+) -> PyResult<Option<(&'static str, &'py PyAny)>> {
+    let mut found: Option<(&'static str, &PyAny)> = None;
+    for &key in keys {
+        let value = match dict.get_item(key)? {
+            Some(value) if !value.is_none() => value,
+            _ => continue,
+        };
+        match found {
+            None => found = Some((key, value)),
+            Some((first, kept)) if !kept.eq(value)? => {
+                return Err(PyValueError::new_err(format!(
+                    "{first:?} and {key:?} are both set, to different values"
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(found)
+}
+
+/// Every key a probe result dict may set: those `ServiceStatus` serializes
+/// to, so that `to_dict` output reads back, and their aliases.
+fn result_keys() -> &'static [&'static str] {
+    static KEYS: OnceLock<Vec<&'static str>> = OnceLock::new();
+    KEYS.get_or_init(|| {
+        let mut keys = serialized_keys().to_vec();
+        for alias in SUBSERVICE_KEYS.into_iter().chain(LABEL_KEYS) {
+            if !keys.contains(&alias) {
+                keys.push(alias);
+            }
+        }
+        keys
+    })
+}
+
+/// The field names `ServiceStatus`'s derived `Deserialize` declares, read
+/// by handing it a deserializer that only records them.
+fn serialized_keys() -> &'static [&'static str] {
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> serde::Deserializer<'de> for FieldNames<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: serde::de::Visitor<'de>>(
+            self,
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("expected a struct"))
+        }
+
+        fn deserialize_struct<V: serde::de::Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(serde::de::Error::custom("only reading field names"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = ServiceStatus::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Rejects keys outside `result_keys`, most likely typos.
+fn check_keys(dict: &PyDict) -> PyResult<()> {
+    let keys = result_keys();
+    for key in dict.keys() {
+        let known = key.extract::<&str>().is_ok_and(|key| keys.contains(&key));
+        if !known {
+            return Err(PyValueError::new_err(format!(
+                "unknown key {}; expected one of {}",
+                key.repr()?,
+                keys.join(", ")
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Extracts a string, naming the type found otherwise.
+fn expect_str(obj: &PyAny) -> PyResult<&str> {
+    obj.extract().map_err(|_| {
+        PyTypeError::new_err(format!(
+            "expected string, got {}",
+            obj.get_type().name().unwrap_or("?")
+        ))
+    })
+}
+
+fn dict_to_status(
+    dict: &PyDict,
+    fallback_name: Option<&str>,
+    depth: usize,
+    reader: &mut Reader<'_>,
+) -> PyResult<ServiceStatus> {
+    let py = dict.py();
+    if reader.strict {
+        if let Err(e) = check_keys(dict) {
+            return Err(reader.locate(py, None, e));
+        }
+    }
+    let name: String = match dict.get_item("name")? {
+        Some(name) => reader.field(py, "name", expect_str(name).map(str::to_owned))?,
+        None => match fallback_name {
+            Some(name) => name.to_owned(),
+            None if reader.strict => {
+                return Err(reader.locate(py, None, PyKeyError::new_err("name")))
+            }
+            None => return Err(PyKeyError::new_err("name")),
+        },
+    };
+    reader.field(py, "name", check_name(&name))?;
+    let status = reader.field(
+        py,
+        "status",
+        result_status(dict.get_item("status")?, reader.lenient),
+    )?;
+    let description = reader
+        .field(
+            py,
+            "description",
+            dict.get_item("description")?.map(expect_str).transpose(),
+        )?
+        .map(|d| reader.sanitize(d));
+    let labels = match reader.field(py, "labels", first_of(dict, &LABEL_KEYS))? {
+        Some((key, labels)) => reader.field(py, key, labels_from_py(key, labels))?,
+        None => HashMap::new(),
+    };
+    let subservices = match first_of(dict, &SUBSERVICE_KEYS)? {
+        Some((key, children)) => subservices_from_py(key, children, depth, reader)?,
+        None => Vec::new(),
+    };
+    let last_checked = match dict.get_item("last_checked")? {
+        Some(t) if !t.is_none() => {
+            Some(reader.field(py, "last_checked", timestamp_from_py("last_checked", t))?)
+        }
+        _ => None,
+    };
+    let latency_ms: Option<f64> = reader.field(
+        py,
+        "latency_ms",
+        dict.get_item("latency_ms")?
+            .map(|l| l.extract())
+            .transpose(),
+    )?;
+    let metadata = match dict.get_item("metadata")? {
+        Some(m) if !m.is_none() => {
+            Some(reader.field(py, "metadata", metadata_from_py("metadata", m))?)
+        }
+        _ => None,
+    };
+    let critical = match dict.get_item("critical")? {
+        Some(c) if !c.is_none() => reader.field(py, "critical", c.extract())?,
+        _ => true,
+    };
+    let flag = |key: &str| -> PyResult<bool> {
+        match dict.get_item(key)? {
+            Some(f) if !f.is_none() => f.extract(),
+            _ => Ok(false),
+        }
+    };
+    let silenced = reader.field(py, "silenced", flag("silenced"))?;
+    let stale = reader.field(py, "stale", flag("stale"))?;
+    let since = match dict.get_item("since")? {
+        Some(t) if !t.is_none() => {
+            Some(reader.field(py, "since", timestamp_from_py("since", t))?)
+        }
+        _ => None,
+    };
+    let uptime: Option<f64> = reader.field(
+        py,
+        "uptime",
+        dict.get_item("uptime")?.map(|u| u.extract()).transpose(),
+    )?;
+    let uptime_samples: Option<usize> = reader.field(
+        py,
+        "uptime_samples",
+        dict.get_item("uptime_samples")?
+            .map(|u| u.extract())
+            .transpose(),
+    )?;
+
+    Ok(ServiceStatus {
+        name,
+        status,
+        description,
+        subservices,
+        labels,
+        silenced,
+        stale,
+        last_checked,
+        latency_ms,
+        metadata,
+        since,
+        uptime,
+        uptime_samples,
+        critical,
+    })
+}
+
+/// Converts a probe result, naming it `fallback_name` if it carries no name
+/// of its own. Results breaking `limits` or with blank names are rejected
+/// with ValueError. In `strict` mode, dicts with unknown keys are rejected
+/// too and errors name the path to the offending element.
+pub fn status_from_py(
+    obj: &PyAny,
+    fallback_name: Option<&str>,
+    lenient: bool,
+    strict: bool,
+    limits: &Limits,
+) -> PyResult<ServiceStatus> {
+    let mut reader = Reader::new(lenient, strict, limits);
+    convert(obj, fallback_name, 0, &mut reader)
+}
+
+/// `status_from_py` for a result nested `depth` levels deep.
+fn convert(
+    obj: &PyAny,
+    fallback_name: Option<&str>,
+    depth: usize,
+    reader: &mut Reader<'_>,
+) -> PyResult<ServiceStatus> {
+    if let Ok(mut s) = obj.extract::<ServiceStatus>() {
+        if let (true, Some(name)) = (s.name.is_empty(), fallback_name) {
+            s.name = name.to_owned();
+        }
+        reader.enter_tree(&mut s, depth)?;
+        return Ok(s);
+    }
+    reader.enter(depth)?;
+    if let Ok(dict) = obj.downcast::<PyDict>() {
+        return dict_to_status(dict, fallback_name, depth, reader);
+    }
+    if let Some((status, description)) = bare_status(obj, reader)? {
+        let name = fallback_name.ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "a bare status such as {} carries no name; only a probe's own result may be one",
+                obj.repr().map_or_else(|_| "?".into(), |r| r.to_string())
+            ))
+        })?;
+        return Ok(ServiceStatus {
+            name: name.to_owned(),
+            status,
+            description,
+            subservices: Vec::new(),
+            labels: HashMap::new(),
+            silenced: false,
+            stale: false,
+            last_checked: None,
+            latency_ms: None,
+            metadata: None,
+            since: None,
+            uptime: None,
+            uptime_samples: None,
+            critical: true,
+        });
+    }
+    attrs_to_status(obj, fallback_name, depth, reader)
+}
+
+/// Return shapes accepted from probes, for error messages.
+const RESULT_SHAPES: &str = "a ServiceStatus, a dict, an object with name and status \
+    attributes, a StatusColor, a status name, a bool, None or a (status, description) tuple";
+
+/// Reads the shorthand results: `True` (Green), `False` (Red), `None`
+/// (Unknown), a `StatusColor` or its name, or a `(status, description)`
+/// tuple of those. Anything else is not a bare status.
+fn bare_status(
+    obj: &PyAny,
+    reader: &Reader<'_>,
+) -> PyResult<Option<(StatusColor, Option<String>)>> {
+    let color = |obj: &PyAny| -> PyResult<Option<StatusColor>> {
+        if let Ok(flag) = obj.downcast::<PyBool>() {
+            return Ok(Some(if flag.is_true() {
+                StatusColor::Green
+            } else {
+                StatusColor::Red
+            }));
+        }
+        if obj.is_none() || obj.is_instance_of::<PyString>() || obj.extract::<StatusColor>().is_ok()
+        {
+            return result_status(Some(obj), reader.lenient).map(Some);
+        }
+        Ok(None)
+    };
+    if let Ok(tuple) = obj.downcast::<PyTuple>() {
+        let shape_error = || {
+            PyTypeError::new_err(format!(
+                "a tuple result must be (status, description), got {}",
+                obj.repr().map_or_else(|_| "?".into(), |r| r.to_string())
+            ))
+        };
+        if tuple.len() != 2 {
+            return Err(shape_error());
+        }
+        let status = color(tuple.get_item(0)?)?.ok_or_else(shape_error)?;
+        let description: Option<&str> = tuple.get_item(1)?.extract().map_err(|_| shape_error())?;
+        return Ok(Some((status, description.map(|d| reader.sanitize(d)))));
+    }
+    Ok(color(obj)?.map(|status| (status, None)))
+}
+
+/// Reads a result exposing `name`, `status` and optionally `description`
+/// and `subservices` as attributes, e.g. a dataclass or a Pydantic model.
+fn attrs_to_status(
+    obj: &PyAny,
+    fallback_name: Option<&str>,
+    depth: usize,
+    reader: &mut Reader<'_>,
+) -> PyResult<ServiceStatus> {
+    let type_name = || obj.get_type().name().unwrap_or("?").to_owned();
+    let optional = |attr: &str| -> PyResult<Option<&PyAny>> {
+        if !obj.hasattr(attr)? {
+            return Ok(None);
+        }
+        let value = obj.getattr(attr)?;
+        Ok((!value.is_none()).then_some(value))
+    };
+    let missing = |attr: &str| {
+        PyAttributeError::new_err(format!(
+            "probe result of type {} has no {attr:?} attribute; expected {RESULT_SHAPES}",
+            type_name()
+        ))
+    };
+
+    let name: String = match (optional("name")?, fallback_name) {
+        (Some(name), _) => name.extract()?,
+        (None, Some(name)) => name.to_owned(),
+        (None, None) => return Err(missing("name")),
+    };
+    check_name(&name)?;
+    if !obj.hasattr("status")? {
+        return Err(missing("status"));
+    }
+    let status = result_status(optional("status")?, reader.lenient)?;
+    let description = optional("description")?
+        .map(|d| d.extract::<&str>())
+        .transpose()?
+        .map(|d| reader.sanitize(d));
+    let subservices = match optional("subservices")? {
+        Some(subservices) => subservices_from_py("subservices", subservices, depth, reader)?,
+        None => Vec::new(),
+    };
+    let last_checked = optional("last_checked")?
+        .map(|t| timestamp_from_py("last_checked", t))
+        .transpose()?;
+    let latency_ms: Option<f64> = optional("latency_ms")?.map(|l| l.extract()).transpose()?;
+    let metadata = optional("metadata")?
+        .map(|m| metadata_from_py("metadata", m))
         .transpose()?;
+    let critical = optional("critical")?
+        .map(|c| c.extract())
+        .transpose()?
+        .unwrap_or(true);
 
     Ok(ServiceStatus {
         name,
-        status: py_status_to_rust(&status_str),
+        status,
         description,
-        subservices: Vec::new(),
+        subservices,
+        labels: HashMap::new(),
+        silenced: false,
+        stale: false,
+        last_checked,
+        latency_ms,
+        metadata,
+        since: None,
+        uptime: None,
+        uptime_samples: None,
+        critical,
     })
 }
 
 impl<'a> std::convert::TryFrom<&'a pyo3::PyAny> for ServiceStatus {
     type Error = PyErr;
     fn try_from(obj: &'a pyo3::PyAny) -> PyResult<Self> {
-        if let Ok(s) = obj.extract::<ServiceStatus>() {
-            return Ok(s);
+        status_from_py(obj, None, false, false, &Limits::untruncated())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_status_names_are_rejected() {
+        Python::with_gil(|py| {
+            let err = py_status_to_rust("purple", false).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+            assert!(err.value(py).to_string().contains("\"purple\""));
+
+            let err = color_from_py(7_i64.into_py(py).as_ref(py)).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+            let err = color_from_py(true.into_py(py).as_ref(py)).unwrap_err();
+            assert!(err.is_instance_of::<PyTypeError>(py));
+        });
+    }
+
+    #[test]
+    fn lenient_reads_unknown_statuses_as_red() {
+        Python::with_gil(|py| {
+            assert_eq!(py_status_to_rust("purple", true).unwrap(), StatusColor::Red);
+            let level = 7_i64.into_py(py);
+            assert_eq!(
+                status_value_from_py(level.as_ref(py), true).unwrap(),
+                StatusColor::Red
+            );
+        });
+    }
+
+    #[test]
+    fn status_names_ignore_case_and_surrounding_whitespace() {
+        for (name, color) in [
+            ("green", StatusColor::Green),
+            ("  Orange\n", StatusColor::Orange),
+            ("\tRED ", StatusColor::Red),
+            ("unKnown", StatusColor::Unknown),
+        ] {
+            assert_eq!(name.parse::<StatusColor>().unwrap(), color, "{name:?}");
         }
-        let dict: &PyDict = obj.downcast()?;
-        dict_to_status(dict)
+        assert!("".parse::<StatusColor>().is_err());
+        assert!("gr een".parse::<StatusColor>().is_err());
+    }
+
+    /// A tree of `name: color` leaves under `root`, for tests.
+    fn tree(root: StatusColor, leaves: &[(&str, StatusColor)]) -> ServiceStatus {
+        let mut status = ServiceStatus::leaf("root".into(), root, None).unwrap();
+        status.subservices = leaves
+            .iter()
+            .map(|&(name, color)| ServiceStatus::leaf(name.into(), color, None).unwrap())
+            .collect();
+        status
+    }
+
+    #[test]
+    fn repr_str_and_eq() {
+        Python::with_gil(|py| {
+            let mut status = tree(StatusColor::Orange, &[("db", StatusColor::Green)]);
+            status.subservices[0].description = Some("ok".into());
+            assert_eq!(
+                status.__repr__(py).unwrap(),
+                "ServiceStatus(name='root', status=ORANGE, subservices=1)"
+            );
+            assert_eq!(status.__str__(), "root: ORANGE\n  db: GREEN (ok)");
+            assert_eq!(StatusColor::Red.__repr__(), "StatusColor.Red");
+            assert_eq!(StatusColor::Red.__str__(), "RED");
+
+            let same = Py::new(py, status.clone()).unwrap();
+            let eq = status.__richcmp__(same.as_ref(py), CompareOp::Eq);
+            assert!(eq.extract::<bool>(py).unwrap());
+
+            let mut other = status.clone();
+            other.subservices[0].status = StatusColor::Red;
+            let other = Py::new(py, other).unwrap();
+            let ne = status.__richcmp__(other.as_ref(py), CompareOp::Ne);
+            assert!(ne.extract::<bool>(py).unwrap());
+
+            let lt = status.__richcmp__(other.as_ref(py), CompareOp::Lt);
+            assert!(lt.is(&py.NotImplemented()));
+        });
+    }
+
+    #[test]
+    fn colors_order_worst_to_best() {
+        use StatusColor::*;
+        assert!(Red < Unknown && Unknown < Orange && Orange < Green);
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            locals
+                .set_item("StatusColor", py.get_type::<StatusColor>())
+                .unwrap();
+            let holds = |expr: &str| {
+                py.eval(expr, None, Some(locals))
+                    .unwrap()
+                    .is_true()
+                    .unwrap()
+            };
+            assert!(holds(
+                "StatusColor.Red < StatusColor.Unknown < StatusColor.Orange < StatusColor.Green"
+            ));
+            assert!(holds("not StatusColor.Red > StatusColor.Orange"));
+            assert!(holds("sorted([StatusColor.Green, StatusColor.Red]) == [StatusColor.Red, StatusColor.Green]"));
+
+            let colors = PyList::new(py, ["orange", "GREEN", "unknown"]);
+            let cls = py.get_type::<StatusColor>();
+            assert_eq!(StatusColor::worst(cls, colors).unwrap(), Unknown);
+            assert_eq!(StatusColor::best(cls, colors).unwrap(), Green);
+            let err = StatusColor::worst(cls, PyList::empty(py)).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+        });
+    }
+
+    #[test]
+    fn int_reads_back_as_the_same_color() {
+        Python::with_gil(|py| {
+            for color in StatusColor::ALL {
+                let int = py
+                    .import("builtins")
+                    .unwrap()
+                    .call_method1("int", (color,))
+                    .unwrap();
+                assert_eq!(color_from_py(int).unwrap(), color, "int({color:?}) = {int}");
+            }
+        });
+    }
+
+    #[test]
+    fn serde_round_trips() {
+        let mut status = tree(StatusColor::Red, &[("db", StatusColor::Green)]);
+        status.description = Some("down".into());
+        status.labels.insert("team".into(), "core".into());
+        status.last_checked = DateTime::from_timestamp(1_700_000_000, 0);
+        status.latency_ms = Some(12.5);
+        status.metadata = serde_json::from_str(r#"{"pool": 4}"#).ok();
+        status.uptime = Some(0.75);
+        status.uptime_samples = Some(4);
+        status.subservices[0].critical = false;
+        status.subservices[0].silenced = true;
+
+        let json = serde_json::to_string(&status).unwrap();
+        let back: ServiceStatus = serde_json::from_str(&json).unwrap();
+        assert!(back == status, "{json}");
+
+        assert_eq!(
+            serde_json::to_string(&StatusColor::Orange).unwrap(),
+            r#""ORANGE""#
+        );
+        let color: StatusColor = serde_json::from_str(r#""UNKNOWN""#).unwrap();
+        assert_eq!(color, StatusColor::Unknown);
+    }
+
+    /// Checks `value` against `schema`, supporting the keywords
+    /// `HEALTH_SCHEMA` uses. Returns the path of the first violation.
+    fn validate(root: &Value, schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let target = reference.trim_start_matches("#/").split('/');
+            let target = target.fold(root, |node, key| &node[key]);
+            return validate(root, target, value, path);
+        }
+        let fail = || Err(format!("{path}: {value} does not match {schema}"));
+        let type_ok = match schema["type"].as_str() {
+            None => true,
+            Some("object") => value.is_object(),
+            Some("array") => value.is_array(),
+            Some("string") => value.is_string(),
+            Some("boolean") => value.is_boolean(),
+            Some("integer") => value.is_i64() || value.is_u64(),
+            Some("number") => value.is_number(),
+            Some(other) => panic!("unsupported type {other}"),
+        };
+        if !type_ok {
+            return fail();
+        }
+        if let Some(options) = schema["enum"].as_array() {
+            if !options.contains(value) {
+                return fail();
+            }
+        }
+        if let Some(n) = value.as_f64() {
+            let below = schema["minimum"].as_f64().is_some_and(|min| n < min);
+            let above = schema["maximum"].as_f64().is_some_and(|max| n > max);
+            if below || above {
+                return fail();
+            }
+        }
+        if let Some(items) = value.as_array() {
+            for (i, item) in items.iter().enumerate() {
+                validate(root, &schema["items"], item, &format!("{path}[{i}]"))?;
+            }
+        }
+        if let Some(object) = value.as_object() {
+            for key in schema["required"].as_array().into_iter().flatten() {
+                if !object.contains_key(key.as_str().unwrap()) {
+                    return Err(format!("{path}: missing {key}"));
+                }
+            }
+            for (key, item) in object {
+                let item_schema = match &schema["properties"][key] {
+                    Value::Null => &schema["additionalProperties"],
+                    item_schema => item_schema,
+                };
+                match item_schema {
+                    Value::Bool(false) => return Err(format!("{path}: unexpected {key:?}")),
+                    Value::Null | Value::Bool(true) => {}
+                    item_schema => validate(root, item_schema, item, &format!("{path}.{key}"))?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn serialized_trees_match_the_schema() {
+        let schema: Value = serde_json::from_str(HEALTH_SCHEMA).unwrap();
+        let properties = schema["$defs"]["ServiceStatus"]["properties"]
+            .as_object()
+            .unwrap();
+        let mut documented: Vec<&str> = properties.keys().map(String::as_str).collect();
+        let mut serialized = serialized_keys().to_vec();
+        documented.sort_unstable();
+        serialized.sort_unstable();
+        assert_eq!(documented, serialized);
+
+        let mut status = tree(StatusColor::Orange, &[("db", StatusColor::Unknown)]);
+        status.description = Some("slow".into());
+        status.labels.insert("team".into(), "core".into());
+        status.stale = true;
+        status.last_checked = DateTime::from_timestamp(1_700_000_000, 0);
+        status.since = status.last_checked;
+        status.latency_ms = Some(3.0);
+        status.metadata = serde_json::from_str(r#"{"version": "1.2"}"#).ok();
+        status.uptime = Some(1.0);
+        status.uptime_samples = Some(10);
+        status.subservices[0].critical = false;
+        status.subservices[0].silenced = true;
+        let value = serde_json::to_value(&status).unwrap();
+        validate(&schema, &schema, &value, "$").unwrap();
+
+        let mut invalid = value.clone();
+        invalid["subservices"][0]["status"] = "PURPLE".into();
+        assert!(validate(&schema, &schema, &invalid, "$").is_err());
+        invalid = value;
+        invalid["extra"] = true.into();
+        assert!(validate(&schema, &schema, &invalid, "$").is_err());
+    }
+
+    #[test]
+    fn reduce_restores_the_whole_tree() {
+        Python::with_gil(|py| {
+            let mut status = tree(StatusColor::Red, &[("db", StatusColor::Orange)]);
+            status.subservices[0]
+                .labels
+                .insert("team".into(), "core".into());
+            status.uptime_samples = Some(2);
+
+            let (cls, (name, color), state) = status.__reduce__(py).unwrap();
+            let restored = cls.call1(py, (name, color)).unwrap();
+            let restored: &PyCell<ServiceStatus> = restored.downcast(py).unwrap();
+            restored.borrow_mut().__setstate__(&state).unwrap();
+            assert!(*restored.borrow() == status);
+
+            let err = restored.borrow_mut().__setstate__("{").unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+
+            for color in StatusColor::ALL {
+                let (getattr, args) = color.__reduce__(py).unwrap();
+                let restored = getattr.call1(py, args).unwrap();
+                assert_eq!(restored.extract::<StatusColor>(py).unwrap(), color);
+            }
+        });
+    }
+
+    #[test]
+    fn merge_combines_trees() {
+        let mut mine = tree(
+            StatusColor::Green,
+            &[("db", StatusColor::Green), ("cache", StatusColor::Orange)],
+        );
+        mine.description = Some("mine".into());
+        mine.labels.insert("team".into(), "core".into());
+        mine.silenced = true;
+        let mut theirs = tree(
+            StatusColor::Orange,
+            &[("queue", StatusColor::Green), ("db", StatusColor::Red)],
+        );
+        theirs.name = "other".into();
+        theirs.description = Some("theirs".into());
+        theirs.labels.insert("team".into(), "infra".into());
+        theirs.labels.insert("tier".into(), "1".into());
+        theirs.subservices[1].description = Some("down".into());
+
+        let merged = mine.merge(&theirs);
+        assert_eq!(merged.name, "root");
+        assert_eq!(merged.status, StatusColor::Orange);
+        assert_eq!(merged.description.as_deref(), Some("mine; theirs"));
+        assert_eq!(merged.labels["team"], "core");
+        assert_eq!(merged.labels["tier"], "1");
+        assert!(!merged.silenced);
+        assert_eq!(
+            merged.flatten(),
+            [
+                ("".into(), StatusColor::Orange, Some("mine; theirs".into())),
+                ("db".into(), StatusColor::Red, Some("down".into())),
+                ("cache".into(), StatusColor::Orange, None),
+                ("queue".into(), StatusColor::Green, None),
+            ]
+        );
+        assert_eq!(mine.subservices.len(), 2, "inputs are left untouched");
+    }
+
+    #[test]
+    fn health_conveniences() {
+        let mut status = tree(
+            StatusColor::Green,
+            &[("db", StatusColor::Green), ("cache", StatusColor::Green)],
+        );
+        assert!(status.is_healthy());
+        assert_eq!(status.worst_descendant().0, "");
+
+        status.subservices[1].subservices = vec![
+            ServiceStatus::leaf("redis".into(), StatusColor::Red, None).unwrap(),
+            ServiceStatus::leaf("memcached".into(), StatusColor::Red, None).unwrap(),
+        ];
+        status.subservices[0].status = StatusColor::Orange;
+        assert!(!status.is_healthy());
+        let (path, worst) = status.worst_descendant();
+        assert_eq!(
+            (path.as_str(), worst.name.as_str()),
+            ("cache.redis", "redis")
+        );
+
+        let counts = status.count_by_status();
+        assert_eq!(counts["GREEN"], 2);
+        assert_eq!(counts["ORANGE"], 1);
+        assert_eq!(counts["RED"], 2);
+        assert_eq!(counts["UNKNOWN"], 0);
+    }
+
+    /// Reads the probe result `source`, a Python expression.
+    fn read(py: Python<'_>, source: &str) -> PyResult<ServiceStatus> {
+        let result = py.eval(source, None, None)?;
+        status_from_py(result, Some("probe"), false, false, &Limits::default())
+    }
+
+    #[test]
+    fn children_and_checks_alias_subservices() {
+        Python::with_gil(|py| {
+            for key in SUBSERVICE_KEYS {
+                let source =
+                    format!("{{'status': 'GREEN', '{key}': [{{'name': 'db', 'status': 'RED'}}]}}");
+                let status = read(py, &source).unwrap();
+                assert_eq!(status.subservices.len(), 1, "{key}");
+                assert_eq!(status.subservices[0].status, StatusColor::Red, "{key}");
+            }
+
+            let same = "{'status': 'GREEN', 'children': [], 'checks': []}";
+            assert!(read(py, same).unwrap().subservices.is_empty());
+            let conflicting =
+                "{'status': 'GREEN', 'children': [], 'checks': [{'name': 'db', 'status': 'RED'}]}";
+            let err = read(py, conflicting).err().unwrap();
+            assert!(err.is_instance_of::<PyValueError>(py));
+        });
+    }
+
+    #[test]
+    fn missing_status_reads_as_unknown() {
+        Python::with_gil(|py| {
+            for source in [
+                "{'name': 'db'}",
+                "{'name': 'db', 'status': None}",
+                "{'name': 'db', 'status': '  '}",
+            ] {
+                assert_eq!(
+                    read(py, source).unwrap().status,
+                    StatusColor::Unknown,
+                    "{source}"
+                );
+            }
+            let err = read(py, "{'name': 'db', 'status': 'purple'}")
+                .err()
+                .unwrap();
+            assert!(err.is_instance_of::<PyValueError>(py));
+        });
+    }
+
+    #[test]
+    fn hash_follows_the_whole_tree() {
+        let status = tree(StatusColor::Green, &[("db", StatusColor::Green)]);
+        let mut same = status.clone();
+        same.latency_ms = Some(4.0);
+        assert_eq!(status.__hash__(), same.__hash__());
+
+        let mut deeper = status.clone();
+        deeper.subservices[0].description = Some("slow".into());
+        assert_ne!(status.__hash__(), deeper.__hash__());
+
+        let mut renamed = status.clone();
+        renamed.subservices[0].name = "cache".into();
+        assert_ne!(status.__hash__(), renamed.__hash__());
     }
 }
@@ -0,0 +1,37 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::Serialize;
+
+/// Version and provenance of this build, captured by `build.rs`.
+#[derive(Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub rustc_version: &'static str,
+    /// Seconds since the Unix epoch.
+    pub build_timestamp: u64,
+}
+
+impl BuildInfo {
+    pub fn get() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("COLONOSCOPY_GIT_SHA"),
+            rustc_version: env!("COLONOSCOPY_RUSTC_VERSION"),
+            build_timestamp: env!("COLONOSCOPY_BUILD_TIMESTAMP").parse().unwrap_or(0),
+        }
+    }
+}
+
+/// `{"version", "git_sha", "rustc_version", "build_timestamp"}` of the
+/// loaded extension. Values that could not be captured read `"unknown"`.
+#[pyfunction]
+pub fn build_info(py: Python<'_>) -> PyResult<&PyDict> {
+    let info = BuildInfo::get();
+    let dict = PyDict::new(py);
+    dict.set_item("version", info.version)?;
+    dict.set_item("git_sha", info.git_sha)?;
+    dict.set_item("rustc_version", info.rustc_version)?;
+    dict.set_item("build_timestamp", info.build_timestamp)?;
+    Ok(dict)
+}
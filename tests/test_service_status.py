@@ -0,0 +1,74 @@
+"""Python-level tests of ServiceStatus and StatusColor, run against the built
+module: `maturin develop && python -m unittest discover tests`."""
+
+import pickle
+import unittest
+
+from colonoscopy import ServiceStatus, StatusColor
+
+
+def tree(cache="down"):
+    return ServiceStatus(
+        "api",
+        StatusColor.Orange,
+        "slow",
+        [
+            ServiceStatus("db", StatusColor.Green),
+            ServiceStatus("cache", StatusColor.Red, cache),
+        ],
+    )
+
+
+class ReprStrEqTest(unittest.TestCase):
+    def test_repr(self):
+        self.assertEqual(
+            repr(tree()), "ServiceStatus(name='api', status=ORANGE, subservices=2)"
+        )
+        self.assertEqual(repr(StatusColor.Green), "StatusColor.Green")
+
+    def test_str(self):
+        self.assertEqual(
+            str(tree()), "api: ORANGE (slow)\n  db: GREEN\n  cache: RED (down)"
+        )
+        self.assertEqual(str(StatusColor.Red), "RED")
+
+    def test_eq_compares_the_whole_tree(self):
+        self.assertEqual(tree(), tree())
+        self.assertNotEqual(tree(cache="flushing"), tree())
+        self.assertTrue(tree(cache="flushing") != tree())
+        self.assertNotEqual(tree(), "api")
+        self.assertEqual(StatusColor.Red, StatusColor.Red)
+        self.assertNotEqual(StatusColor.Red, StatusColor.Orange)
+
+
+
+class PickleTest(unittest.TestCase):
+    def test_trees_survive_a_round_trip(self):
+        self.assertEqual(pickle.loads(pickle.dumps(tree())), tree())
+        empty = ServiceStatus("api", StatusColor.Unknown)
+        self.assertEqual(pickle.loads(pickle.dumps(empty)), empty)
+
+    def test_colors_unpickle_as_the_same_member(self):
+        for color in (
+            StatusColor.Red,
+            StatusColor.Orange,
+            StatusColor.Green,
+            StatusColor.Unknown,
+        ):
+            self.assertEqual(pickle.loads(pickle.dumps(color)), color)
+
+
+
+class HashTest(unittest.TestCase):
+    def test_equal_trees_are_found_in_sets(self):
+        expected = tree()
+        self.assertIn(expected, {tree()})
+        self.assertEqual(hash(expected), hash(tree()))
+        self.assertNotIn(tree(cache="flushing"), {expected})
+
+    def test_colors_are_hashable(self):
+        self.assertEqual({StatusColor.Red: "page"}[StatusColor.Red], "page")
+
+
+if __name__ == "__main__":
+    unittest.main()